@@ -0,0 +1,232 @@
+use super::{structures, variables};
+/// Value of an argument, before it's bound to a parameter
+enum Argument {
+    /// Text from the command line, parsed as the type of the parameter (if it's annotated)
+    Text(String),
+    /// Value that has already been parsed from JSON
+    Value(variables::Complex),
+}
+/// Arguments of the entry function, given by position or by parameter name
+#[derive(Default)]
+pub struct Arguments {
+    positional: Vec<Argument>,
+    named: Vec<(String, Argument)>,
+}
+impl Arguments {
+    /// Add an argument from the command line
+    ///
+    /// `NAME=VALUE` is bound to the parameter `NAME` if the function has one, and anything else is
+    /// the next positional argument. With `json`, every value is parsed as JSON.
+    pub fn push(
+        &mut self,
+        function: &structures::Function,
+        argument: &str,
+        json: bool,
+    ) -> Result<(), String> {
+        let (name, value) = match argument.find('=') {
+            Some(offset)
+                if function
+                    .input
+                    .iter()
+                    .any(|name| *name == argument[..offset]) =>
+            {
+                (Some(&argument[..offset]), &argument[offset + 1..])
+            }
+            _ => (None, argument),
+        };
+        let value = if json {
+            Argument::Value(
+                serde_json::from_str(value)
+                    .map_err(|e| e.to_string())
+                    .and_then(variables::Complex::from_json)
+                    .map_err(|e| format!("Argument {} is not valid JSON: {}", argument, e))?,
+            )
+        } else {
+            Argument::Text(value.to_string())
+        };
+        match name {
+            Some(name) => self.named.push((name.to_string(), value)),
+            None => self.positional.push(value),
+        }
+        Ok(())
+    }
+    /// Add arguments from JSON: an array of positional arguments, or an object of named ones
+    pub fn push_json(&mut self, content: &str) -> Result<(), String> {
+        let value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        match value {
+            serde_json::Value::Array(array) => {
+                for value in array {
+                    self.positional
+                        .push(Argument::Value(variables::Complex::from_json(value)?));
+                }
+            }
+            serde_json::Value::Object(object) => {
+                for (name, value) in object {
+                    self.named
+                        .push((name, Argument::Value(variables::Complex::from_json(value)?)));
+                }
+            }
+            _ => return Err(String::from("Arguments should be a JSON array or object")),
+        }
+        Ok(())
+    }
+    /// Input of `function`, in the order of its parameters
+    pub fn bind(self, function: &structures::Function) -> Result<Vec<variables::Complex>, String> {
+        if self.positional.len() > function.input.len() {
+            return Err(format!(
+                "Function `{}{}` expects {} arguments, found {}",
+                function.name,
+                function.signature(),
+                function.input.len(),
+                self.positional.len() + self.named.len()
+            ));
+        }
+        let mut input = vec![None; function.input.len()];
+        for (index, argument) in self.positional.into_iter().enumerate() {
+            input[index] = Some(parse(function, index, argument)?);
+        }
+        for (name, argument) in self.named {
+            let index = function
+                .input
+                .iter()
+                .position(|variable_name| *variable_name == name)
+                .ok_or_else(|| {
+                    format!(
+                        "Function `{}{}` has no parameter `{}`",
+                        function.name,
+                        function.signature(),
+                        name
+                    )
+                })?;
+            if input[index].is_some() {
+                return Err(format!(
+                    "Argument `{}` of function `{}` is given more than once",
+                    name, function.name
+                ));
+            }
+            input[index] = Some(parse(function, index, argument)?);
+        }
+        let missing = function
+            .input
+            .iter()
+            .zip(&input)
+            .filter(|(_, value)| value.is_none())
+            .map(|(variable_name, _)| format!("`{}`", variable_name))
+            .collect::<Vec<String>>();
+        if !missing.is_empty() {
+            return Err(format!(
+                "Function `{}{}` is missing {}",
+                function.name,
+                function.signature(),
+                missing.join(", ")
+            ));
+        }
+        Ok(input.into_iter().flatten().collect())
+    }
+}
+/// Value of the `index`th parameter of `function`
+fn parse(
+    function: &structures::Function,
+    index: usize,
+    argument: Argument,
+) -> Result<variables::Complex, String> {
+    let text = match argument {
+        Argument::Text(text) => text,
+        // Checked against the annotation when the function is called
+        Argument::Value(value) => return Ok(value),
+    };
+    match function.input_type[index] {
+        None => Ok(variables::Complex::Primitive(
+            variables::Primitive::Literal(text),
+        )),
+        Some(variable_type) => typed(&text, variable_type).ok_or_else(|| {
+            format!(
+                "Parameter `{}` of function `{}` expects {}, found `{}`",
+                function.input[index], function.name, variable_type, text
+            )
+        }),
+    }
+}
+/// Parse text from the command line as a value of `variable_type`
+fn typed(text: &str, variable_type: variables::Type) -> Option<variables::Complex> {
+    let primitive = match variable_type {
+        variables::Type::Nil if text.is_empty() || text == "nil" => variables::Primitive::Nil,
+        variables::Type::String => variables::Primitive::Literal(text.to_string()),
+        variables::Type::Int => variables::Primitive::Int(text.parse().ok()?),
+        variables::Type::Float => variables::Primitive::Float(text.parse().ok()?),
+        variables::Type::Bool => variables::Primitive::Bool(text.parse().ok()?),
+        variables::Type::Function => {
+            let (script, function) = variables::function_reference(text)?;
+            variables::Primitive::Function(script, function)
+        }
+        variables::Type::Bytes => variables::Primitive::Bytes(base64::decode(text).ok()?),
+        variables::Type::Stack | variables::Type::Map => {
+            let value = serde_json::from_str(text).ok()?;
+            let value = variables::Complex::from_json(value).ok()?;
+            return Some(value).filter(|value| variables::Type::of(value) == variable_type);
+        }
+        variables::Type::Nil => return None,
+    };
+    Some(variables::Complex::Primitive(primitive))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn bind_arguments() {
+        let script =
+            structures::new_script("main<>(name,count:Int,tags:Stack,ratio:Float){}").unwrap();
+        let function = &script["main"];
+        let bind = |arguments: &[&str], json: Option<&str>| {
+            let mut result = Arguments::default();
+            for argument in arguments {
+                result.push(function, argument, false)?;
+            }
+            if let Some(json) = json {
+                result.push_json(json)?;
+            }
+            result
+                .bind(function)
+                .map(|input| input.iter().map(|i| i.to_string()).collect::<Vec<String>>())
+        };
+        assert_eq!(
+            bind(&["a=b", "tags=[1,2]", "count=3"], Some("{\"ratio\":0.5}")),
+            Ok(vec![
+                String::from("a=b"),
+                String::from("3"),
+                String::from("[1,2]"),
+                String::from("0.5")
+            ])
+        );
+        assert_eq!(
+            bind(&["x", "count=three"], None),
+            Err(String::from(
+                "Parameter `count` of function `main` expects Int, found `three`"
+            ))
+        );
+        assert_eq!(
+            bind(&["x", "1"], None),
+            Err(String::from(
+                "Function `main<>(name,count:Int,tags:Stack,ratio:Float)` is missing `tags`, `ratio`"
+            ))
+        );
+        assert_eq!(
+            bind(&["x", "name=y"], None),
+            Err(String::from(
+                "Argument `name` of function `main` is given more than once"
+            ))
+        );
+        assert_eq!(
+            bind(&[], Some("[\"x\",1,[],0.5,1]")),
+            Err(String::from(
+                "Function `main<>(name,count:Int,tags:Stack,ratio:Float)` expects 4 arguments, found 5"
+            ))
+        );
+        assert_eq!(
+            bind(&[], Some("{\"size\":1}")),
+            Err(String::from(
+                "Function `main<>(name,count:Int,tags:Stack,ratio:Float)` has no parameter `size`"
+            ))
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use super::{structures, variables};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+/// Check calls between scripts against the signatures of the called functions
+///
+/// Returns a message for every problem found. The type of an argument is only known if it's a literal,
+/// an annotated parameter or the result of an annotated function, so everything else passes.
+pub fn check(script_map: &HashMap<String, structures::Script>) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut script_names = script_map.keys().collect::<Vec<&String>>();
+    script_names.sort();
+    for script_name in script_names {
+        let script = &script_map[script_name];
+        let mut function_names = script.keys().collect::<Vec<&String>>();
+        function_names.sort();
+        for function_name in function_names {
+            let function = &script[function_name];
+            let location = format!("{}@{}", function_name, script_name);
+            let mut known = HashMap::new();
+            for (variable_name, variable_type) in function.input.iter().zip(&function.input_type) {
+                if let Some(variable_type) = variable_type {
+                    known.insert(variable_name.to_string(), *variable_type);
+                }
+            }
+            check_body(
+                &function.process,
+                &mut known,
+                script_map,
+                &location,
+                &mut errors,
+            );
+            if let (Some(output), Some(output_type)) = (&function.output, function.output_type) {
+                match known.get(output) {
+                    Some(found) if *found != output_type => errors.push(format!(
+                        "{}: Result `{}` of function `{}` expects {}, found {}",
+                        location, output, function.name, output_type, found
+                    )),
+                    _ => (),
+                }
+            }
+        }
+    }
+    errors
+}
+fn check_body(
+    body: &[Arc<RwLock<structures::Expression>>],
+    known: &mut HashMap<String, variables::Type>,
+    script_map: &HashMap<String, structures::Script>,
+    location: &str,
+    errors: &mut Vec<String>,
+) {
+    for expression in body {
+        let expression = expression.read().unwrap();
+        let mut result_type = None;
+        if let structures::Operation::External(script_name, function_name) = &expression.operation {
+            match script_map
+                .get(script_name)
+                .and_then(|script| script.get(function_name))
+            {
+                None => errors.push(format!(
+                    "{}: Function {}@{} does not exist",
+                    location, function_name, script_name
+                )),
+                Some(function) if function.input.len() != expression.variables.len() => errors
+                    .push(format!(
+                        "{}: Function `{}` expects {} arguments, found {}",
+                        location,
+                        function.name,
+                        function.input.len(),
+                        expression.variables.len()
+                    )),
+                Some(function) => {
+                    for ((variable_name, variable_type), argument) in function
+                        .input
+                        .iter()
+                        .zip(&function.input_type)
+                        .zip(&expression.variables)
+                    {
+                        match (variable_type, argument_type(argument, known)) {
+                            (Some(variable_type), Some(found)) if *variable_type != found => errors
+                                .push(format!(
+                                    "{}: Parameter `{}` of function `{}` expects {}, found {}",
+                                    location, variable_name, function.name, variable_type, found
+                                )),
+                            _ => (),
+                        }
+                    }
+                    result_type = function.output_type;
+                }
+            }
+        }
+        match &expression.to {
+            structures::ExpressionTo::ToVar(variable_name) => match result_type {
+                Some(result_type) => {
+                    known.insert(variable_name.to_string(), result_type);
+                }
+                None => {
+                    known.remove(variable_name);
+                }
+            },
+            structures::ExpressionTo::ToBlock(block)
+            | structures::ExpressionTo::Collect(_, _, block) => {
+                // Assignments of an instance are only visible inside the instance
+                let mut block_known = known.clone();
+                for variable_name in &["this", "key", "value"] {
+                    block_known.remove(*variable_name);
+                }
+                check_body(block, &mut block_known, script_map, location, errors);
+                if let structures::ExpressionTo::Collect(variable_name, ..) = &expression.to {
+                    known.insert(variable_name.to_string(), variables::Type::Stack);
+                }
+            }
+            structures::ExpressionTo::Nil => (),
+        }
+    }
+}
+fn argument_type(
+    argument: &str,
+    known: &HashMap<String, variables::Type>,
+) -> Option<variables::Type> {
+    if argument.starts_with('"') && argument.ends_with('"') {
+        Some(variables::Type::String)
+    } else if variables::is_number(argument) {
+        Some(variables::Type::of(&variables::Complex::Primitive(
+            variables::Primitive::from_number(argument),
+        )))
+    } else if variables::function_reference(argument).is_some() {
+        Some(variables::Type::Function)
+    } else {
+        known.get(argument).copied()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn check_annotations() {
+        let mut script_map = HashMap::new();
+        script_map.insert(
+            String::from("lib"),
+            structures::new_script("sum<total:Int>(a:Int,b:Int){}name<n:String>(){}").unwrap(),
+        );
+        script_map.insert(
+            String::from("main"),
+            structures::new_script(
+                "main<>(s:String){a=sum@lib(1,2);b=sum@lib(a,s);c=name@lib();d=sum@lib(c,1.5);e=sum@lib(1);f=missing@lib();stack123()=>{g=sum@lib(this,a);};}",
+            ).unwrap(),
+        );
+        assert_eq!(
+            check(&script_map),
+            vec![
+                "main@main: Parameter `b` of function `sum` expects Int, found String",
+                "main@main: Parameter `a` of function `sum` expects Int, found String",
+                "main@main: Parameter `b` of function `sum` expects Int, found Float",
+                "main@main: Function `sum` expects 2 arguments, found 1",
+                "main@main: Function missing@lib does not exist",
+            ]
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+/// Source of time for the kernel, in milliseconds
+pub trait Clock: std::fmt::Debug + Send + Sync {
+    fn now(&self) -> u64;
+    /// Called by the kernel when there is nothing to do until `time`
+    fn wait_until(&self, time: u64);
+}
+/// Wall clock, counting from the UNIX epoch
+#[derive(Debug, Default)]
+pub struct SystemClock;
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0)
+    }
+    fn wait_until(&self, time: u64) {
+        // Only wait for a short moment, so that the kernel can still handle new messages
+        std::thread::sleep(Duration::from_millis(
+            time.saturating_sub(self.now()).min(1),
+        ));
+    }
+}
+/// Clock that starts from zero and jumps forward whenever the kernel is waiting
+#[derive(Debug, Default)]
+pub struct VirtualClock {
+    now: AtomicU64,
+}
+impl Clock for VirtualClock {
+    fn now(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+    fn wait_until(&self, time: u64) {
+        self.now.fetch_max(time, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,139 @@
+use super::error::{Error, Kind};
+use super::{format, structures, variables};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+/// Write every module into `directory`, one formatted file per module, and return the file of
+/// every module
+///
+/// Calls are already resolved to module names, so each file declares its module and imports the
+/// modules it calls by their names. The files can be run without the original scripts, for example
+/// with `awsl run build/main.awsl`.
+pub fn compile(
+    scripts: &HashMap<String, structures::Script>,
+    directory: &Path,
+) -> Result<HashMap<String, PathBuf>, Error> {
+    let files = file_names(scripts);
+    std::fs::create_dir_all(directory).map_err(|e| {
+        Error::new(
+            Kind::Output,
+            format!("Unable to create {}: {}", directory.display(), e),
+        )
+    })?;
+    let mut paths = HashMap::new();
+    for (module_name, script) in scripts {
+        let mut source = format!("module \"{}\";", module_name);
+        let mut called = BTreeSet::new();
+        for function in script.values() {
+            called_modules(&function.process, &mut called);
+        }
+        for called_name in called
+            .iter()
+            .filter(|called_name| *called_name != module_name)
+        {
+            if let Some(file) = files.get(called_name) {
+                source.push_str(&format!("import \"{}\" as {};", file, called_name));
+            }
+        }
+        let mut function_names = script.keys().collect::<Vec<&String>>();
+        function_names.sort();
+        for function_name in function_names {
+            source.push_str(&script[function_name].to_string());
+        }
+        let path = directory.join(&files[module_name]);
+        std::fs::write(&path, format::format(&source)).map_err(|e| {
+            Error::new(
+                Kind::Output,
+                format!("Unable to write {}: {}", path.display(), e),
+            )
+        })?;
+        paths.insert(module_name.to_string(), path);
+    }
+    Ok(paths)
+}
+/// File name of every module: the name of the original file for modules named after their path,
+/// and `NAME.awsl` otherwise
+fn file_names(scripts: &HashMap<String, structures::Script>) -> HashMap<String, String> {
+    let mut module_names = scripts.keys().collect::<Vec<&String>>();
+    module_names.sort();
+    let mut used = BTreeSet::new();
+    let mut files = HashMap::new();
+    for module_name in module_names {
+        let stem = match Path::new(module_name).file_stem() {
+            Some(stem) if module_name.contains(std::path::MAIN_SEPARATOR) => {
+                stem.to_string_lossy().to_string()
+            }
+            _ => module_name.to_string(),
+        };
+        let mut file = format!("{}.awsl", stem);
+        let mut count = 1;
+        while used.contains(&file) {
+            count += 1;
+            file = format!("{}-{}.awsl", stem, count);
+        }
+        used.insert(file.to_string());
+        files.insert(module_name.to_string(), file);
+    }
+    files
+}
+/// Add the modules called in `body`, or passed as function references, to `called`
+fn called_modules(body: &[Arc<RwLock<structures::Expression>>], called: &mut BTreeSet<String>) {
+    for expression in body {
+        let expression = expression.read().unwrap();
+        if let structures::Operation::External(script_name, _) = &expression.operation {
+            called.insert(script_name.to_string());
+        }
+        for variable in &expression.variables {
+            if !variable.starts_with('"') {
+                if let Some((script_name, _)) = variables::function_reference(variable) {
+                    called.insert(script_name);
+                }
+            }
+        }
+        match &expression.to {
+            structures::ExpressionTo::ToBlock(block)
+            | structures::ExpressionTo::Collect(_, _, block) => called_modules(block, called),
+            _ => (),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader;
+    #[test]
+    fn round_trip() {
+        let directory = std::env::temp_dir().join(format!("awsl-compile-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("main.awsl"),
+            "import \"util.awsl\" as u;\nmain<r>(){r=twice@u(2);y=stack123()=><z>{z=add(this,1);};}",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("util.awsl"),
+            "module \"util\";\ntwice<r:Int>(x:Int){r=add(x,x);}",
+        )
+        .unwrap();
+        let mut modules = loader::Modules::default();
+        let main_name = modules.load(&directory.join("main.awsl")).unwrap();
+        let scripts = modules.finish();
+        let paths = compile(&scripts, &directory.join("build")).unwrap();
+        assert_eq!(paths["util"], directory.join("build").join("util.awsl"));
+        let compiled = std::fs::read_to_string(&paths[&main_name]).unwrap();
+        let mut modules = loader::Modules::default();
+        let compiled_name = modules.load(&paths[&main_name]).unwrap();
+        let compiled_scripts = modules.finish();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(compiled_name, main_name);
+        assert!(compiled.contains("import \"util.awsl\" as util;"));
+        assert_eq!(
+            compiled_scripts[&main_name]["main"].to_string(),
+            scripts[&main_name]["main"].to_string()
+        );
+        assert_eq!(
+            compiled_scripts["util"]["twice"].to_string(),
+            "twice<r:Int>(x:Int){r=add(x,x);}"
+        );
+    }
+}
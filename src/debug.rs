@@ -0,0 +1,446 @@
+use super::{format, kernel, structures, variables};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, Write};
+use std::sync::{Arc, RwLock};
+/// Line of the first expression of every function
+const FIRST_LINE: usize = 2;
+const HELP: &str = "\
+Lines count from the definition of a function (line 1), as `list` shows them.
+
+break FUNCTION[@MODULE][:LINE]   Pause before an expression (the first one by default)
+delete [BREAKPOINT]              Remove a breakpoint, or every breakpoint
+breakpoints                      List breakpoints
+list FUNCTION[@MODULE]           Show a function with its line numbers
+packages                         List packages waiting to be executed
+vars N                           Show the variables of the Nth package
+step                             Execute the next package, and pause again
+continue                         Run until a breakpoint is reached
+help                             Show this message
+quit                             Stop the script";
+/// Where an expression is: its function, and its line as `list` shows it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    pub module: String,
+    pub function: String,
+    pub line: usize,
+}
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}@{}:{}", self.function, self.module, self.line)
+    }
+}
+/// Expressions the kernel pauses before, written as `FUNCTION[@MODULE][:LINE]`
+///
+/// Without a module, the breakpoint applies to functions of that name in every module. Without a
+/// line, it applies to the first expression of the function.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub function: String,
+    pub module: Option<String>,
+    pub line: Option<usize>,
+}
+impl Breakpoint {
+    pub fn from_str(breakpoint: &str) -> Result<Self, String> {
+        let (reference, line) = match breakpoint.rfind(':') {
+            Some(offset) if !breakpoint[..offset].ends_with(':') => (
+                &breakpoint[..offset],
+                Some(
+                    breakpoint[offset + 1..]
+                        .parse()
+                        .map_err(|_| format!("Invalid line in breakpoint {}", breakpoint))?,
+                ),
+            ),
+            _ => (breakpoint, None),
+        };
+        let (module, function) = match variables::function_reference(reference) {
+            Some((module, function)) => (Some(module), function),
+            None => (None, reference.to_string()),
+        };
+        if function.is_empty() {
+            return Err(format!("Invalid breakpoint {}", breakpoint));
+        }
+        Ok(Self {
+            function,
+            module,
+            line,
+        })
+    }
+    pub fn matches(&self, location: &Location) -> bool {
+        self.function == location.function
+            && self
+                .module
+                .as_ref()
+                .is_none_or(|module| *module == location.module)
+            && self.line.unwrap_or(FIRST_LINE) == location.line
+    }
+}
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.function)?;
+        if let Some(module) = &self.module {
+            write!(f, "@{}", module)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        Ok(())
+    }
+}
+/// A package waiting to be executed, as the debugger shows it
+#[derive(Debug)]
+pub struct PackageState {
+    pub identifier: String,
+    /// Where the expression of the package is, unless it doesn't belong to a registered script
+    pub location: Option<Location>,
+    pub expression: String,
+    /// Every variable the expression can see, including those of the caller of a block
+    pub variables: BTreeMap<String, variables::Complex>,
+    /// Whether the package waits for `sleep` to end
+    pub sleeping: bool,
+}
+impl std::fmt::Display for PackageState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "{} {} {}", self.identifier, location, self.expression)?,
+            None => write!(f, "{} ? {}", self.identifier, self.expression)?,
+        }
+        if self.sleeping {
+            write!(f, " (sleeping)")?;
+        }
+        Ok(())
+    }
+}
+/// Line of every expression of a function, as `list` shows it
+///
+/// The definition is line 1, and every expression (and the end of every block) is on a line of
+/// its own, as laid out by `awsl fmt`.
+pub fn lines(function: &structures::Function) -> Vec<(Arc<RwLock<structures::Expression>>, usize)> {
+    let mut result = Vec::new();
+    let mut line = 1;
+    body_lines(&function.process, &mut line, &mut result);
+    result
+}
+fn body_lines(
+    body: &[Arc<RwLock<structures::Expression>>],
+    line: &mut usize,
+    result: &mut Vec<(Arc<RwLock<structures::Expression>>, usize)>,
+) {
+    for expression in body {
+        *line += 1;
+        result.push((Arc::clone(expression), *line));
+        match &expression.read().unwrap().to {
+            structures::ExpressionTo::ToBlock(block)
+            | structures::ExpressionTo::Collect(_, _, block) => {
+                body_lines(block, line, result);
+                *line += 1;
+            }
+            _ => (),
+        }
+    }
+}
+/// Add the location of every expression of a script, by the address of the expression
+pub fn index(
+    module_name: &str,
+    script: &structures::Script,
+    locations: &mut HashMap<usize, Location>,
+) {
+    for function in script.values() {
+        for (expression, line) in lines(function) {
+            locations.insert(
+                Arc::as_ptr(&expression) as usize,
+                Location {
+                    module: module_name.to_string(),
+                    function: function.name.to_string(),
+                    line,
+                },
+            );
+        }
+    }
+}
+/// Terminal prompt that drives a kernel created with `Config::debug`
+pub struct Debugger<'a> {
+    kernel: &'a kernel::Kernel,
+    scripts: &'a HashMap<String, structures::Script>,
+    breakpoints: Vec<Breakpoint>,
+    /// Packages of the last pause
+    packages: Vec<PackageState>,
+    /// Whether every package has been executed
+    finished: bool,
+}
+impl<'a> Debugger<'a> {
+    pub fn new(
+        kernel: &'a kernel::Kernel,
+        scripts: &'a HashMap<String, structures::Script>,
+    ) -> Self {
+        Self {
+            kernel,
+            scripts,
+            breakpoints: Vec::new(),
+            packages: Vec::new(),
+            finished: false,
+        }
+    }
+    /// Read commands until every package has been executed, and return `false` if the user quit
+    /// (or `input` ended) instead, so that the kernel should be stopped
+    pub fn run(mut self, input: impl BufRead, mut output: impl Write) -> bool {
+        let mut lines = input.lines();
+        match self.kernel.debug(kernel::Message::Inspect) {
+            Some(packages) => self.packages = packages,
+            None => return true,
+        }
+        let _ = writeln!(output, "Paused before {}. Try help", self.next());
+        loop {
+            let _ = write!(output, "(debug) ");
+            let _ = output.flush();
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => return false,
+            };
+            match self.command(line.trim()) {
+                Ok(Some(message)) => {
+                    let _ = writeln!(output, "{}", message);
+                }
+                Ok(None) => return false,
+                Err(e) => {
+                    let _ = writeln!(output, "{}", e);
+                }
+            }
+            if self.finished {
+                let _ = writeln!(output, "Finished");
+                return true;
+            }
+        }
+    }
+    /// Run a command, and return what should be shown, or `None` if the script should stop
+    fn command(&mut self, command: &str) -> Result<Option<String>, String> {
+        let (name, argument) = match command.find(char::is_whitespace) {
+            Some(offset) => (&command[..offset], command[offset..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            "break" | "b" if !argument.is_empty() => {
+                let breakpoint = Breakpoint::from_str(argument)?;
+                if !self.exists(&breakpoint) {
+                    return Err(format!("No expression matches {}", breakpoint));
+                }
+                let message = format!("Breakpoint {}", breakpoint);
+                self.breakpoints.push(breakpoint);
+                self.kernel
+                    .send_message(kernel::Message::Break(self.breakpoints.clone()));
+                Ok(Some(message))
+            }
+            "delete" | "d" => {
+                if argument.is_empty() {
+                    self.breakpoints.clear();
+                } else {
+                    let breakpoint = Breakpoint::from_str(argument)?;
+                    let count = self.breakpoints.len();
+                    self.breakpoints.retain(|i| *i != breakpoint);
+                    if self.breakpoints.len() == count {
+                        return Err(format!("No breakpoint {}", breakpoint));
+                    }
+                }
+                self.kernel
+                    .send_message(kernel::Message::Break(self.breakpoints.clone()));
+                Ok(Some(String::from("Deleted")))
+            }
+            "breakpoints" => Ok(Some(
+                self.breakpoints
+                    .iter()
+                    .map(|breakpoint| breakpoint.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            )),
+            "list" | "l" if !argument.is_empty() => self.list(argument).map(Some),
+            "packages" | "p" => Ok(Some(
+                self.packages
+                    .iter()
+                    .enumerate()
+                    .map(|(index, package)| format!("#{} {}", index, package))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+            )),
+            "vars" | "v" => {
+                let package = argument
+                    .trim_start_matches('#')
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| self.packages.get(index))
+                    .ok_or_else(|| format!("No package {}. Try packages", argument))?;
+                Ok(Some(
+                    package
+                        .variables
+                        .iter()
+                        .map(|(variable_name, variable_content)| {
+                            format!("{} = {}", variable_name, variable_content)
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ))
+            }
+            "step" | "s" => self.pause(kernel::Message::Step),
+            "continue" | "c" => self.pause(kernel::Message::Continue),
+            "help" | "h" => Ok(Some(HELP.to_string())),
+            "quit" | "q" => Ok(None),
+            _ => Err(format!("Unknown command {}. Try help", command)),
+        }
+    }
+    /// Send a command that runs packages, and describe where the kernel paused again
+    fn pause(&mut self, command: kernel::Message) -> Result<Option<String>, String> {
+        match self.kernel.debug(command) {
+            Some(packages) => {
+                self.packages = packages;
+                let next = self.next();
+                Ok(Some(
+                    match self
+                        .packages
+                        .first()
+                        .and_then(|package| package.location.as_ref())
+                    {
+                        Some(location)
+                            if self
+                                .breakpoints
+                                .iter()
+                                .any(|breakpoint| breakpoint.matches(location)) =>
+                        {
+                            format!("Breakpoint {}", next)
+                        }
+                        _ => format!("Paused before {}", next),
+                    },
+                ))
+            }
+            None => {
+                self.finished = true;
+                Ok(Some(String::from("Every package has been executed")))
+            }
+        }
+    }
+    /// The package that `step` executes next
+    fn next(&self) -> String {
+        match self.packages.first() {
+            Some(package) => package.to_string(),
+            None => String::from("nothing (only sleeping packages are left)"),
+        }
+    }
+    /// Whether any expression of the loaded scripts matches `breakpoint`
+    fn exists(&self, breakpoint: &Breakpoint) -> bool {
+        self.scripts.iter().any(|(module_name, script)| {
+            script.values().any(|function| {
+                lines(function).into_iter().any(|(_, line)| {
+                    breakpoint.matches(&Location {
+                        module: module_name.to_string(),
+                        function: function.name.to_string(),
+                        line,
+                    })
+                })
+            })
+        })
+    }
+    /// Formatted source of a function, with line numbers
+    fn list(&self, reference: &str) -> Result<String, String> {
+        let (module_name, function_name) = match variables::function_reference(reference) {
+            Some((module_name, function_name)) => (Some(module_name), function_name),
+            None => (None, reference.to_string()),
+        };
+        let mut module_names = self.scripts.keys().collect::<Vec<&String>>();
+        module_names.sort();
+        let function = module_names
+            .into_iter()
+            .filter(|name| {
+                module_name
+                    .as_ref()
+                    .is_none_or(|module_name| module_name == *name)
+            })
+            .find_map(|name| self.scripts[name].get(&function_name))
+            .ok_or_else(|| format!("Function {} not found", reference))?;
+        Ok(format::format(&function.to_string())
+            .lines()
+            .enumerate()
+            .map(|(index, line)| format!("{:>4} {}", index + 1, line))
+            .collect::<Vec<String>>()
+            .join("\n"))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn breakpoints() {
+        assert_eq!(
+            Breakpoint::from_str("lib::twice:3"),
+            Ok(Breakpoint {
+                function: String::from("twice"),
+                module: Some(String::from("lib")),
+                line: Some(3)
+            })
+        );
+        assert_eq!(Breakpoint::from_str("main").unwrap().to_string(), "main");
+        assert!(Breakpoint::from_str("main:x").is_err());
+        let script = structures::new_script(
+            "main<r>(){a=add(1,2);ys=stack123()=><y>{y=add(this,a);};r=len(ys);}",
+        )
+        .unwrap();
+        let function = &script["main"];
+        let layout = format::format(&function.to_string());
+        let layout = layout.lines().collect::<Vec<&str>>();
+        for (expression, line) in lines(function) {
+            let expression = expression.read().unwrap().to_string();
+            // The line starts with the call of the expression, with or without an assignment
+            let call = expression.split("=>").next().unwrap();
+            let call = call.trim_end_matches(';');
+            let call = call.rsplit('=').next().unwrap();
+            assert!(
+                layout[line - 1].replace(' ', "").contains(call),
+                "{} should be on line {}",
+                expression,
+                line
+            );
+        }
+        assert_eq!(
+            lines(function)
+                .into_iter()
+                .map(|(_, line)| line)
+                .collect::<Vec<usize>>(),
+            vec![2, 3, 4, 6]
+        );
+    }
+    #[test]
+    fn session() {
+        let _ = pretty_env_logger::try_init();
+        let script = structures::new_script(
+            "main<r>(){a=add(1,2);b=add(a,3);stack123()=>{c=concat(this,b);};r=add(b,1);}",
+        )
+        .unwrap();
+        let kernel = kernel::Kernel::with_config(kernel::Config {
+            debug: true,
+            ..kernel::Config::default()
+        });
+        kernel.send_message(kernel::Message::NewScript(
+            String::from("main"),
+            Arc::clone(&script),
+        ));
+        let (packages, result) =
+            kernel::ExpressionPackage::from_function(&script["main"], Vec::new()).unwrap();
+        for package in packages {
+            kernel.send_package(package);
+        }
+        kernel.send_message(kernel::Message::ExitGracefully);
+        let mut scripts = HashMap::new();
+        scripts.insert(String::from("main"), script);
+        let input = "break main:5\nbreak main:9\nlist main\nstep\nvars 0\ncontinue\npackages\nvars 2\ncontinue\ndelete\ncontinue\n";
+        let mut output = Vec::new();
+        assert!(Debugger::new(&kernel, &scripts).run(input.as_bytes(), &mut output));
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("No expression matches main:9"));
+        assert!(output.contains("   3     b = add(a, 3);"));
+        assert!(output.contains("Paused before "));
+        assert!(output.contains("a = 3\n"));
+        assert!(output.contains("Breakpoint "));
+        assert!(output.contains("#2 "));
+        assert!(output.contains(" main@main:5 c=concat(this,b);\n"));
+        assert!(output.contains("b = 6\nthis = 3\n"));
+        assert!(output.ends_with("Every package has been executed\nFinished\n"));
+        assert_eq!(kernel.grace_stop(), None);
+        assert_eq!(result.recv().unwrap().to_string(), "7");
+    }
+}
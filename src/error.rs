@@ -0,0 +1,69 @@
+/// Kind of an error that stops the command line interface
+///
+/// Every kind exits with its own code, so that callers can tell them apart. Scripts choose their
+/// own codes with `exit`, which may overlap with these.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    /// Invalid command line, such as a missing script
+    Usage,
+    /// A script can't be read
+    NotFound,
+    /// A script, an import or the manifest is malformed
+    Invalid,
+    /// The function that should be executed doesn't exist
+    FunctionNotFound,
+    /// Arguments don't match the parameters of the function
+    Arguments,
+    /// `check` found calls that don't match the called functions
+    Check,
+    /// `fmt --check` found scripts that aren't formatted
+    Unformatted,
+    /// A test failed
+    TestFailed,
+    /// Output can't be written
+    Output,
+    /// The result of the function should be the exit code, but isn't an integer
+    Result,
+    /// A script failed while running, such as reading an undefined variable
+    Runtime,
+}
+impl Kind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Kind::Usage => 2,
+            Kind::NotFound => 3,
+            Kind::Invalid => 4,
+            Kind::FunctionNotFound => 5,
+            Kind::Arguments => 6,
+            Kind::Check => 7,
+            Kind::Unformatted => 8,
+            Kind::TestFailed => 9,
+            Kind::Output => 10,
+            Kind::Result => 11,
+            Kind::Runtime => 12,
+        }
+    }
+}
+#[derive(Debug)]
+pub struct Error {
+    pub kind: Kind,
+    pub message: String,
+}
+impl Error {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+    /// Print the message, and exit with the code of the kind
+    pub fn exit(&self) -> ! {
+        eprintln!("{}", self.message);
+        std::process::exit(self.kind.exit_code())
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
@@ -1,25 +1,450 @@
-use super::{functions, variables};
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
-pub type BuiltInFunction = dyn Fn(&[Arc<RwLock<variables::Primitive>>]) -> variables::Complex;
+use super::{functions, kernel, structures, variables};
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+pub type BuiltInFunction =
+    dyn Fn(&[Arc<RwLock<variables::Complex>>], &Context) -> variables::Complex;
 pub fn builtin_hashmap() -> HashMap<String, Box<BuiltInFunction>> {
     let mut result: HashMap<String, Box<BuiltInFunction>> = HashMap::new();
-    let built_in_commands_list = vec![
+    for i in builtin_list() {
+        result.insert(i.name, i.function);
+    }
+    result
+}
+/// Usage and description of every builtin, by name
+pub fn builtin_docs() -> HashMap<String, &'static str> {
+    builtin_list()
+        .into_iter()
+        .map(|i| (i.name, i.doc))
+        .collect()
+}
+fn builtin_list() -> Vec<BuiltInCmd> {
+    vec![
         BuiltInCmd {
             name: "print".to_string(),
             function: Box::new(functions::print),
+            doc: "print(value): Write a value on its own line",
         },
         BuiltInCmd {
             name: "stack123".to_string(),
             function: Box::new(functions::stack123),
+            doc: "stack123(): Stack of \"1\", \"2\" and \"3\"",
         },
-    ];
-    for i in built_in_commands_list {
-        result.insert(i.name, i.function);
-    }
-    result
+        BuiltInCmd {
+            name: "read_line".to_string(),
+            function: Box::new(functions::read_line),
+            doc: "read_line(): Read a single line from stdin, without its line ending. Returns `Nil` at EOF",
+        },
+        BuiltInCmd {
+            name: "stdin_lines".to_string(),
+            function: Box::new(functions::stdin_lines),
+            doc: "stdin_lines(): Read stdin until EOF, and return a stack with one element per line",
+        },
+        BuiltInCmd {
+            name: "env".to_string(),
+            function: Box::new(functions::env),
+            doc: "env(name): Value of an environment variable, or `Nil` if it isn't set",
+        },
+        BuiltInCmd {
+            name: "env_all".to_string(),
+            function: Box::new(functions::env_all),
+            doc: "env_all(): All environment variables, as a stack of `NAME=value`",
+        },
+        BuiltInCmd {
+            name: "args".to_string(),
+            function: Box::new(functions::args),
+            doc: "args(): Command line arguments passed with `-a`",
+        },
+        BuiltInCmd {
+            name: "cwd".to_string(),
+            function: Box::new(functions::cwd),
+            doc: "cwd(): Current working directory",
+        },
+        BuiltInCmd {
+            name: "exit".to_string(),
+            function: Box::new(functions::exit),
+            doc: "exit(code?): Stop the kernel, and exit with the code (`0` by default)",
+        },
+        BuiltInCmd {
+            name: "assert".to_string(),
+            function: Box::new(functions::assert),
+            doc: "assert(condition, reason?): Fail unless the condition is `true`",
+        },
+        BuiltInCmd {
+            name: "assert_eq".to_string(),
+            function: Box::new(functions::assert_eq),
+            doc: "assert_eq(left, right, reason?): Fail unless both values are equal",
+        },
+        BuiltInCmd {
+            name: "fail".to_string(),
+            function: Box::new(functions::fail),
+            doc: "fail(reason?): Fail with the reason",
+        },
+        BuiltInCmd {
+            name: "now".to_string(),
+            function: Box::new(functions::now),
+            doc: "now(): Current time of the kernel clock, in milliseconds",
+        },
+        BuiltInCmd {
+            name: "sleep".to_string(),
+            function: Box::new(functions::sleep),
+            doc: "sleep(milliseconds): Pause the current function or block",
+        },
+        BuiltInCmd {
+            name: "elapsed".to_string(),
+            function: Box::new(functions::elapsed),
+            doc: "elapsed(start): Milliseconds passed since `start` (a value returned by `now`)",
+        },
+        BuiltInCmd {
+            name: "random_int".to_string(),
+            function: Box::new(functions::random_int),
+            doc: "random_int(lo, hi): Random integer between `lo` and `hi`, both inclusive",
+        },
+        BuiltInCmd {
+            name: "random_float".to_string(),
+            function: Box::new(functions::random_float),
+            doc: "random_float(): Random float in `[0, 1)`",
+        },
+        BuiltInCmd {
+            name: "shuffle".to_string(),
+            function: Box::new(functions::shuffle),
+            doc: "shuffle(stack): Copy of the stack in random order",
+        },
+        BuiltInCmd {
+            name: "choice".to_string(),
+            function: Box::new(functions::choice),
+            doc: "choice(stack): Random element of the stack, or `Nil` if it is empty",
+        },
+        BuiltInCmd {
+            name: "json_parse".to_string(),
+            function: Box::new(functions::json_parse),
+            doc: "json_parse(text): Parse a JSON string into a value",
+        },
+        BuiltInCmd {
+            name: "json_stringify".to_string(),
+            function: Box::new(functions::json_stringify),
+            doc: "json_stringify(value): Serialize a value into a JSON string",
+        },
+        BuiltInCmd {
+            name: "map_new".to_string(),
+            function: Box::new(functions::map_new),
+            doc: "map_new(): Empty map",
+        },
+        BuiltInCmd {
+            name: "get".to_string(),
+            function: Box::new(functions::get),
+            doc: "get(collection, key): Value of `key` in a map, or the element at an index of a stack. Returns `Nil` if there is no such key or index",
+        },
+        BuiltInCmd {
+            name: "set".to_string(),
+            function: Box::new(functions::set),
+            doc: "set(map, key, value): Copy of the map, with `key` set to the value",
+        },
+        BuiltInCmd {
+            name: "keys".to_string(),
+            function: Box::new(functions::keys),
+            doc: "keys(map): Keys of the map, in order",
+        },
+        BuiltInCmd {
+            name: "values".to_string(),
+            function: Box::new(functions::values),
+            doc: "values(map): Values of the map, in the order of their keys",
+        },
+        BuiltInCmd {
+            name: "has".to_string(),
+            function: Box::new(functions::has),
+            doc: "has(map, key): Whether the map contains `key`",
+        },
+        BuiltInCmd {
+            name: "add".to_string(),
+            function: Box::new(functions::add),
+            doc: "add(a, b): Sum of two numbers. The result is a float if either of them is",
+        },
+        BuiltInCmd {
+            name: "concat".to_string(),
+            function: Box::new(functions::concat),
+            doc: "concat(a, b): Two stacks joined together, or two values joined as text",
+        },
+        BuiltInCmd {
+            name: "len".to_string(),
+            function: Box::new(functions::len),
+            doc: "len(value): Number of elements of a stack or a map, or number of characters of a string",
+        },
+        BuiltInCmd {
+            name: "reduce".to_string(),
+            function: Box::new(functions::reduce),
+            doc: "reduce(stack, function, initial?): Combine the elements of a stack with a builtin (by name) or a function reference, from the first to the last",
+        },
+        BuiltInCmd {
+            name: "base64_encode".to_string(),
+            function: Box::new(functions::base64_encode),
+            doc: "base64_encode(value): Base64 of bytes (or of the UTF-8 bytes of a string)",
+        },
+        BuiltInCmd {
+            name: "base64_decode".to_string(),
+            function: Box::new(functions::base64_decode),
+            doc: "base64_decode(text): Bytes of a base64 string",
+        },
+        BuiltInCmd {
+            name: "hex_encode".to_string(),
+            function: Box::new(functions::hex_encode),
+            doc: "hex_encode(value): Lowercase hex of bytes (or of the UTF-8 bytes of a string)",
+        },
+        BuiltInCmd {
+            name: "hex_decode".to_string(),
+            function: Box::new(functions::hex_decode),
+            doc: "hex_decode(text): Bytes of a hex string",
+        },
+        BuiltInCmd {
+            name: "bytes_len".to_string(),
+            function: Box::new(functions::bytes_len),
+            doc: "bytes_len(bytes): Number of bytes",
+        },
+        BuiltInCmd {
+            name: "slice".to_string(),
+            function: Box::new(functions::slice),
+            doc: "slice(value, start, end?): Part of bytes, a stack or a string, from `start` until `end` (exclusive, the end by default)",
+        },
+        BuiltInCmd {
+            name: "read_file".to_string(),
+            function: Box::new(functions::read_file),
+            doc: "read_file(path): Content of a file as a string",
+        },
+        BuiltInCmd {
+            name: "read_bytes".to_string(),
+            function: Box::new(functions::read_bytes),
+            doc: "read_bytes(path): Content of a file as bytes",
+        },
+        BuiltInCmd {
+            name: "write_file".to_string(),
+            function: Box::new(functions::write_file),
+            doc: "write_file(path, value): Write a value to a file, and return whether it has been written. Bytes are written as they are, everything else as text",
+        },
+        BuiltInCmd {
+            name: "ref".to_string(),
+            function: Box::new(functions::function_ref),
+            doc: "ref(function): Reference to a function, from `function@script` (or `script::function`) or a string of the same form",
+        },
+        BuiltInCmd {
+            name: "call".to_string(),
+            function: Box::new(functions::call),
+            doc: "call(function, arguments...): Call a function reference with the rest of the arguments, and return its result",
+        },
+    ]
 }
 struct BuiltInCmd {
     name: String,
     function: Box<BuiltInFunction>,
+    /// Usage, such as `get(collection, key)`, and what the builtin does
+    doc: &'static str,
+}
+/// Everything a builtin may need besides its arguments
+pub struct Context<'a> {
+    /// Where the builtin should write to
+    pub output: Output,
+    pub config: &'a kernel::Config,
+    signal: Cell<Option<Signal>>,
+    seed: u64,
+    rng: RefCell<Option<rand::rngs::StdRng>>,
+}
+impl<'a> Context<'a> {
+    pub fn new(output: Output, config: &'a kernel::Config, seed: u64) -> Self {
+        Self {
+            output,
+            config,
+            signal: Cell::new(None),
+            seed,
+            rng: RefCell::new(None),
+        }
+    }
+    /// Random number generator of the package, seeded by its identifier
+    pub fn rng(&self) -> RefMut<'_, rand::rngs::StdRng> {
+        RefMut::map(self.rng.borrow_mut(), |rng| {
+            rng.get_or_insert_with(|| rand::SeedableRng::seed_from_u64(self.seed))
+        })
+    }
+    /// Ask the kernel to do something once the builtin has returned
+    pub fn signal(&self, signal: Signal) {
+        self.signal.set(Some(signal));
+    }
+    pub fn take_signal(&self) -> Option<Signal> {
+        self.signal.take()
+    }
+    /// Arguments of the builtin `name`, if there are at least `required` of them and each one is
+    /// accepted by `accepts` at its position (arguments after the end of `accepts` aren't checked)
+    ///
+    /// Fails otherwise, and returns `None`.
+    pub fn arguments<'b>(
+        &self,
+        name: &str,
+        args: &'b [Arc<RwLock<variables::Complex>>],
+        required: usize,
+        accepts: &[Accept],
+    ) -> Option<&'b [Arc<RwLock<variables::Complex>>]> {
+        if args.len() < required {
+            self.signal(Signal::Fail(format!(
+                "Function `{}` expects {} arguments, found {}",
+                name,
+                required,
+                args.len()
+            )));
+            return None;
+        }
+        for (position, (arg, accept)) in args.iter().zip(accepts).enumerate() {
+            let arg = arg.read().unwrap();
+            if !accept.accepts(&arg) {
+                self.signal(Signal::Fail(format!(
+                    "Argument {} of function `{}` expects {}, found {}",
+                    position + 1,
+                    name,
+                    accept,
+                    variables::Type::of(&arg)
+                )));
+                return None;
+            }
+        }
+        Some(args)
+    }
+}
+/// What a builtin accepts as one of its arguments
+#[derive(Clone, Copy, Debug)]
+pub enum Accept {
+    /// An integer, or a string of one
+    Integer,
+    /// A value of one of the types
+    Types(&'static [variables::Type]),
+}
+impl Accept {
+    fn accepts(self, value: &variables::Complex) -> bool {
+        match self {
+            Accept::Integer => match value {
+                variables::Complex::Primitive(value) => value.as_integer().is_some(),
+                _ => false,
+            },
+            Accept::Types(types) => types.contains(&variables::Type::of(value)),
+        }
+    }
+}
+impl std::fmt::Display for Accept {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Accept::Integer => write!(f, "an integer"),
+            Accept::Types(types) => write!(
+                f,
+                "{}",
+                types
+                    .iter()
+                    .map(|value_type| value_type.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" or ")
+            ),
+        }
+    }
+}
+/// Request from a builtin to the kernel
+pub enum Signal {
+    /// Stop the kernel, and exit the process with the code
+    Exit(i32),
+    /// Stop the kernel, because the builtin failed for the reason
+    Fail(String),
+    /// Continue the package after the number of milliseconds, without holding the thread
+    Sleep(u64),
+    /// Call a function with the arguments. Its result becomes the result of the builtin.
+    Call(String, String, Vec<variables::Complex>),
+    /// Combine the initial value with every element in order, using the builtin or function.
+    /// Its result becomes the result of the builtin.
+    Reduce(
+        structures::Operation,
+        variables::Complex,
+        Vec<variables::Complex>,
+    ),
+}
+/// Destination of everything printed by a package
+#[derive(Clone, Debug)]
+pub enum Output {
+    Stdout,
+    #[cfg(test)]
+    Memory(Arc<Mutex<String>>),
+    /// Output of the `usize`th instance of an ordered `=>` block
+    Ordered(Arc<OrderedOutput>, usize),
+}
+impl Output {
+    pub fn write_line(&self, line: &str) {
+        self.write(&format!("{}\n", line));
+    }
+    fn write(&self, text: &str) {
+        match self {
+            Output::Stdout => print!("{}", text),
+            #[cfg(test)]
+            Output::Memory(memory) => memory.lock().unwrap().push_str(text),
+            Output::Ordered(ordered, index) => ordered.write(*index, text),
+        }
+    }
+}
+/// Output shared by every instance of a `=>` block when ordered output is enabled
+///
+/// Text written by the instance at the head of the queue is passed through immediately,
+/// while the other instances are buffered until all instances before them have finished.
+#[derive(Debug)]
+pub struct OrderedOutput {
+    parent: Output,
+    state: Mutex<OrderedState>,
+}
+#[derive(Debug, Default)]
+struct OrderedState {
+    next: usize,
+    pending: BTreeMap<usize, String>,
+    finished: BTreeSet<usize>,
+}
+impl OrderedOutput {
+    pub fn new(parent: Output) -> Self {
+        Self {
+            parent,
+            state: Mutex::new(OrderedState::default()),
+        }
+    }
+    fn write(&self, index: usize, text: &str) {
+        let mut state = self.state.lock().unwrap();
+        if index == state.next {
+            self.parent.write(text);
+        } else {
+            state.pending.entry(index).or_default().push_str(text);
+        }
+    }
+    /// Mark the `index`th instance as finished and flush everything that is now in order
+    pub fn finish(&self, index: usize) {
+        let mut guard = self.state.lock().unwrap();
+        let state = &mut *guard;
+        state.finished.insert(index);
+        while state.finished.remove(&state.next) {
+            state.next += 1;
+            if let Some(text) = state.pending.remove(&state.next) {
+                self.parent.write(&text);
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn ordered_output() {
+        let memory = Arc::new(Mutex::new(String::new()));
+        let ordered = Arc::new(OrderedOutput::new(Output::Memory(Arc::clone(&memory))));
+        let first = Output::Ordered(Arc::clone(&ordered), 0);
+        let second = Output::Ordered(Arc::clone(&ordered), 1);
+        let third = Output::Ordered(Arc::clone(&ordered), 2);
+        third.write_line("c");
+        second.write_line("b");
+        first.write_line("a");
+        assert_eq!(*memory.lock().unwrap(), "a\n");
+        ordered.finish(2);
+        assert_eq!(*memory.lock().unwrap(), "a\n");
+        ordered.finish(0);
+        assert_eq!(*memory.lock().unwrap(), "a\nb\n");
+        second.write_line("bb");
+        assert_eq!(*memory.lock().unwrap(), "a\nb\nbb\n");
+        ordered.finish(1);
+        assert_eq!(*memory.lock().unwrap(), "a\nb\nbb\nc\n");
+    }
 }
@@ -0,0 +1,148 @@
+use super::loader;
+const INDENT: &str = "    ";
+/// Lay out a script in the canonical style of `awsl fmt`
+///
+/// Whitespace outside of strings means nothing to the parser, so the script is stripped first
+/// and then laid out again: one declaration or expression per line, blocks indented by four
+/// spaces, and a blank line between functions. A `#!` line is kept as it is.
+pub fn format(script: &str) -> String {
+    let (shebang, script) = loader::split_shebang(script);
+    let mut script = loader::strip_whitespace(script);
+    let mut result = shebang.to_string();
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    let mut declarations = false;
+    for keyword in &["module\"", "import\""] {
+        while script.starts_with(keyword) {
+            let end = match script.find(';') {
+                Some(end) => end,
+                None => break,
+            };
+            let content = &script[keyword.len()..end];
+            result.push_str(&keyword[..keyword.len() - 1]);
+            result.push_str(" \"");
+            match content.rfind("\"as") {
+                Some(offset) if *keyword == "import\"" => {
+                    result.push_str(&content[..offset]);
+                    result.push_str("\" as ");
+                    result.push_str(&content[offset + 3..]);
+                }
+                _ => result.push_str(content),
+            }
+            result.push_str(";\n");
+            script = script[end + 1..].to_string();
+            declarations = true;
+        }
+    }
+    if declarations && !script.is_empty() {
+        result.push('\n');
+    }
+    let mut layout = Layout::default();
+    let chars = script.chars().collect::<Vec<char>>();
+    let mut offset = 0;
+    while offset < chars.len() {
+        let c = chars[offset];
+        if layout.in_string {
+            layout.push(c);
+            if c == '"' {
+                layout.in_string = false;
+            }
+            offset += 1;
+            continue;
+        }
+        match c {
+            '"' => {
+                layout.push(c);
+                layout.in_string = true;
+            }
+            '{' => {
+                layout.push_str(" {");
+                layout.newline();
+                layout.depth += 1;
+            }
+            '}' => {
+                layout.depth = layout.depth.saturating_sub(1);
+                layout.newline();
+                layout.push('}');
+                if chars.get(offset + 1) == Some(&';') {
+                    layout.push(';');
+                    offset += 1;
+                }
+                layout.newline();
+                if layout.depth == 0 {
+                    layout.output.push('\n');
+                }
+            }
+            ';' => {
+                layout.push(';');
+                layout.newline();
+            }
+            ',' => layout.push_str(", "),
+            '=' if chars.get(offset + 1) == Some(&'>') => {
+                layout.push_str(" =>");
+                if chars.get(offset + 2) == Some(&'<') {
+                    layout.push(' ');
+                }
+                offset += 1;
+            }
+            '=' => layout.push_str(" = "),
+            _ => layout.push(c),
+        }
+        offset += 1;
+    }
+    layout.newline();
+    result.push_str(layout.output.trim_end());
+    result.push('\n');
+    result
+}
+#[derive(Default)]
+struct Layout {
+    output: String,
+    /// Content of the current line, without indentation
+    line: String,
+    depth: usize,
+    in_string: bool,
+}
+impl Layout {
+    fn push(&mut self, c: char) {
+        self.line.push(c);
+    }
+    fn push_str(&mut self, content: &str) {
+        self.line.push_str(content);
+    }
+    /// End the current line, if there's anything on it
+    fn newline(&mut self) {
+        if self.line.is_empty() {
+            return;
+        }
+        for _ in 0..self.depth {
+            self.output.push_str(INDENT);
+        }
+        self.output.push_str(&self.line);
+        self.output.push('\n');
+        self.line.clear();
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn layout() {
+        let script = "module \"main\";import \"lib.awsl\" as lib;\nmain<r:Int>(a,b){x=f@lib(a, \"a, b\");y=stack123()=><z>{z=add(this,1);};stack123()=>{print(this);};}\nempty<>(){}";
+        let formatted = format(script);
+        assert_eq!(
+            formatted,
+            "module \"main\";\nimport \"lib.awsl\" as lib;\n\nmain<r:Int>(a, b) {\n    x = f@lib(a, \"a, b\");\n    y = stack123() => <z> {\n        z = add(this, 1);\n    };\n    stack123() => {\n        print(this);\n    };\n}\n\nempty<>() {\n}\n"
+        );
+        assert_eq!(format(&formatted), formatted);
+        assert_eq!(
+            loader::strip_whitespace(&formatted),
+            loader::strip_whitespace(script)
+        );
+        assert_eq!(
+            format("#!/usr/bin/env awsl run\nmain<>(){exit(0);}"),
+            "#!/usr/bin/env awsl run\nmain<>() {\n    exit(0);\n}\n"
+        );
+    }
+}
@@ -1,21 +1,857 @@
-use super::variables;
+use super::{exec, structures, variables};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::io::BufRead;
 use std::sync::{Arc, RwLock};
-pub fn print(args: &[Arc<RwLock<variables::Primitive>>]) -> variables::Complex {
-    println!(
-        "{}",
-        if let variables::Primitive::Literal(content) = &*args[0].read().unwrap() {
-            &content
-        } else {
-            ""
-        }
-    );
+pub fn print(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let value = match context.arguments("print", args, 1, &[]) {
+        Some([value, ..]) => value,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    context
+        .output
+        .write_line(&value.read().unwrap().to_string());
     variables::Complex::Primitive(variables::Primitive::Nil)
 }
-pub fn stack123(_: &[Arc<RwLock<variables::Primitive>>]) -> variables::Complex {
-    println!("you have called stack123.");
+pub fn stack123(
+    _: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    context.output.write_line("you have called stack123.");
     variables::Complex::Stack(vec![
-        variables::Primitive::Literal(String::from("1")),
-        variables::Primitive::Literal(String::from("2")),
-        variables::Primitive::Literal(String::from("3")),
+        variables::Complex::Primitive(variables::Primitive::Literal(String::from("1"))),
+        variables::Complex::Primitive(variables::Primitive::Literal(String::from("2"))),
+        variables::Complex::Primitive(variables::Primitive::Literal(String::from("3"))),
     ])
 }
+/// Read a single line from stdin, without its line ending
+///
+/// Returns `Nil` at EOF
+pub fn read_line(_: &[Arc<RwLock<variables::Complex>>], _: &exec::Context) -> variables::Complex {
+    let mut line = String::new();
+    variables::Complex::Primitive(match std::io::stdin().lock().read_line(&mut line) {
+        Ok(0) => variables::Primitive::Nil,
+        Ok(_) => {
+            trim_line_ending(&mut line);
+            variables::Primitive::Literal(line)
+        }
+        Err(e) => {
+            log::error!("Unable to read from stdin: {}", e);
+            variables::Primitive::Nil
+        }
+    })
+}
+/// Read stdin until EOF, and return a stack with one element per line
+///
+/// This is a bulk read: nothing is returned until stdin is closed, and every line is held in
+/// memory. Use `read_line` to handle lines as they arrive.
+pub fn stdin_lines(_: &[Arc<RwLock<variables::Complex>>], _: &exec::Context) -> variables::Complex {
+    let mut lines = Vec::new();
+    for line in std::io::stdin().lock().lines() {
+        match line {
+            Ok(line) => lines.push(variables::Complex::Primitive(
+                variables::Primitive::Literal(line),
+            )),
+            Err(e) => {
+                log::error!("Unable to read from stdin: {}", e);
+                break;
+            }
+        }
+    }
+    variables::Complex::Stack(lines)
+}
+/// Value of an environment variable, or `Nil` if it isn't set
+pub fn env(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let name = match context.arguments("env", args, 1, &[STRING]) {
+        Some([name, ..]) => name,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(
+        if let variables::Complex::Primitive(variables::Primitive::Literal(name)) =
+            &*name.read().unwrap()
+        {
+            match std::env::var(name) {
+                Ok(value) => variables::Primitive::Literal(value),
+                Err(_) => variables::Primitive::Nil,
+            }
+        } else {
+            variables::Primitive::Nil
+        },
+    )
+}
+/// All environment variables, as a stack of `NAME=value`
+pub fn env_all(_: &[Arc<RwLock<variables::Complex>>], _: &exec::Context) -> variables::Complex {
+    variables::Complex::Stack(
+        std::env::vars_os()
+            .map(|(name, value)| {
+                variables::Complex::Primitive(variables::Primitive::Literal(format!(
+                    "{}={}",
+                    name.to_string_lossy(),
+                    value.to_string_lossy()
+                )))
+            })
+            .collect(),
+    )
+}
+/// Command line arguments passed with `-a`
+pub fn args(_: &[Arc<RwLock<variables::Complex>>], context: &exec::Context) -> variables::Complex {
+    variables::Complex::Stack(
+        context
+            .config
+            .arguments
+            .iter()
+            .map(|argument| {
+                variables::Complex::Primitive(variables::Primitive::Literal(argument.to_string()))
+            })
+            .collect(),
+    )
+}
+/// Current working directory
+pub fn cwd(_: &[Arc<RwLock<variables::Complex>>], _: &exec::Context) -> variables::Complex {
+    variables::Complex::Primitive(match std::env::current_dir() {
+        Ok(path) => variables::Primitive::Literal(path.to_string_lossy().to_string()),
+        Err(e) => {
+            log::error!("Unable to get current directory: {}", e);
+            variables::Primitive::Nil
+        }
+    })
+}
+/// Stop the kernel, and exit with the code (`0` by default)
+pub fn exit(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    use std::convert::TryFrom;
+    context.signal(match context.arguments("exit", args, 0, &[INTEGER]) {
+        Some([code, ..]) => match integer(code) {
+            Some(code) => match i32::try_from(code) {
+                Ok(code) => exec::Signal::Exit(code),
+                Err(_) => exec::Signal::Fail(format!("Exit code {} is out of range", code)),
+            },
+            None => exec::Signal::Fail(String::from("Exit code is not an integer")),
+        },
+        Some(_) => exec::Signal::Exit(0),
+        None => return variables::Complex::Primitive(variables::Primitive::Nil),
+    });
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Fail unless the first argument is `true`, with the second argument as the reason (if any)
+pub fn assert(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (condition, message) = match context.arguments("assert", args, 1, &[BOOL]) {
+        Some([condition, message @ ..]) => (condition, message.first()),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    if let variables::Complex::Primitive(variables::Primitive::Bool(false)) =
+        &*condition.read().unwrap()
+    {
+        context.signal(exec::Signal::Fail(reason(message, "Assertion failed")));
+    }
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Fail unless the first two arguments are equal, with the third argument as the reason (if any)
+pub fn assert_eq(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (left, right, message) = match context.arguments("assert_eq", args, 2, &[]) {
+        Some([left, right, message @ ..]) => (left, right, message.first()),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    let left = left.read().unwrap();
+    let right = right.read().unwrap();
+    if *left != *right {
+        context.signal(exec::Signal::Fail(format!(
+            "{}: {} ({}) != {} ({})",
+            reason(message, "Assertion failed"),
+            left,
+            variables::Type::of(&left),
+            right,
+            variables::Type::of(&right)
+        )));
+    }
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Fail with the first argument as the reason (if any)
+pub fn fail(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    context.signal(exec::Signal::Fail(reason(args.first(), "Failed")));
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Current time of the kernel clock, in milliseconds
+pub fn now(_: &[Arc<RwLock<variables::Complex>>], context: &exec::Context) -> variables::Complex {
+    variables::Complex::Primitive(variables::Primitive::Int(context.config.clock.now() as i64))
+}
+/// Pause the current function or block for a number of milliseconds
+pub fn sleep(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let duration = match context.arguments("sleep", args, 1, &[INTEGER]) {
+        Some([duration, ..]) => duration,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    context.signal(match milliseconds(duration) {
+        Some(duration) => exec::Signal::Sleep(duration),
+        None => exec::Signal::Fail(String::from(
+            "Function `sleep` expects a duration of at least 0",
+        )),
+    });
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Milliseconds passed since `start` (a value returned by `now`)
+pub fn elapsed(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let start = match context.arguments("elapsed", args, 1, &[INTEGER]) {
+        Some([start, ..]) => start,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match milliseconds(start) {
+        Some(start) => {
+            variables::Primitive::Int(context.config.clock.now().saturating_sub(start) as i64)
+        }
+        None => {
+            context.signal(exec::Signal::Fail(String::from(
+                "Function `elapsed` expects a value returned by `now`",
+            )));
+            variables::Primitive::Nil
+        }
+    })
+}
+/// Random integer between `lo` and `hi`, both inclusive
+pub fn random_int(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (lo, hi) = match context.arguments("random_int", args, 2, &[INTEGER, INTEGER]) {
+        Some([lo, hi, ..]) => (integer(lo), integer(hi)),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match (lo, hi) {
+        (Some(lo), Some(hi)) if lo <= hi => {
+            variables::Primitive::Int(context.rng().gen_range(lo, hi + 1))
+        }
+        _ => {
+            context.signal(exec::Signal::Fail(String::from(
+                "Function `random_int` expects `lo` to be at most `hi`",
+            )));
+            variables::Primitive::Nil
+        }
+    })
+}
+/// Random float in `[0, 1)`
+pub fn random_float(
+    _: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    variables::Complex::Primitive(variables::Primitive::Float(context.rng().gen()))
+}
+/// Copy of the stack in random order
+pub fn shuffle(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let stack = match context.arguments("shuffle", args, 1, &[STACK]) {
+        Some([stack, ..]) => stack,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    if let variables::Complex::Stack(stack) = &*stack.read().unwrap() {
+        let mut stack = stack.clone();
+        stack.shuffle(&mut *context.rng());
+        variables::Complex::Stack(stack)
+    } else {
+        variables::Complex::Primitive(variables::Primitive::Nil)
+    }
+}
+/// Random element of the stack, or `Nil` if it is empty
+pub fn choice(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let stack = match context.arguments("choice", args, 1, &[STACK]) {
+        Some([stack, ..]) => stack,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    if let variables::Complex::Stack(stack) = &*stack.read().unwrap() {
+        if let Some(element) = stack.choose(&mut *context.rng()) {
+            return element.clone();
+        }
+    }
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Parse a JSON string into a value
+pub fn json_parse(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let content = match context.arguments("json_parse", args, 1, &[STRING]) {
+        Some([content, ..]) => content,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    if let variables::Complex::Primitive(variables::Primitive::Literal(content)) =
+        &*content.read().unwrap()
+    {
+        match serde_json::from_str(content).map_err(|e| e.to_string()) {
+            Ok(value) => match variables::Complex::from_json(value) {
+                Ok(result) => return result,
+                Err(e) => log::error!("Unable to convert JSON: {}", e),
+            },
+            Err(e) => log::error!("Unable to parse JSON: {}", e),
+        }
+    }
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Serialize a value into a JSON string
+pub fn json_stringify(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let value = match context.arguments("json_stringify", args, 1, &[]) {
+        Some([value, ..]) => value,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(variables::Primitive::Literal(
+        value.read().unwrap().to_json().to_string(),
+    ))
+}
+/// Empty map
+pub fn map_new(_: &[Arc<RwLock<variables::Complex>>], _: &exec::Context) -> variables::Complex {
+    variables::Complex::Map(std::collections::BTreeMap::new())
+}
+/// Value of `key` in a map, or the element at an index of a stack
+///
+/// Returns `Nil` if there is no such key or index
+pub fn get(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (collection, key_or_index) = match context.arguments("get", args, 2, &[COLLECTION]) {
+        Some([collection, key_or_index, ..]) => (collection, key_or_index),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    match &*collection.read().unwrap() {
+        variables::Complex::Map(map) => map.get(&key(key_or_index)).cloned(),
+        variables::Complex::Stack(stack) => integer(key_or_index)
+            .filter(|index| *index >= 0)
+            .and_then(|index| stack.get(index as usize).cloned()),
+        _ => None,
+    }
+    .unwrap_or(variables::Complex::Primitive(variables::Primitive::Nil))
+}
+/// Copy of the map, with `key` set to the value
+pub fn set(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (map, key_value, value) = match context.arguments("set", args, 3, &[MAP]) {
+        Some([map, key_value, value, ..]) => (map, key_value, value),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    if let variables::Complex::Map(map) = &*map.read().unwrap() {
+        let mut map = map.clone();
+        map.insert(key(key_value), value.read().unwrap().clone());
+        variables::Complex::Map(map)
+    } else {
+        variables::Complex::Primitive(variables::Primitive::Nil)
+    }
+}
+/// Keys of the map, in order
+pub fn keys(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let map = match context.arguments("keys", args, 1, &[MAP]) {
+        Some([map, ..]) => map,
+        _ => return variables::Complex::Stack(Vec::new()),
+    };
+    if let variables::Complex::Map(map) = &*map.read().unwrap() {
+        variables::Complex::Stack(
+            map.keys()
+                .map(|key| {
+                    variables::Complex::Primitive(variables::Primitive::Literal(key.to_string()))
+                })
+                .collect(),
+        )
+    } else {
+        variables::Complex::Stack(Vec::new())
+    }
+}
+/// Values of the map, in the order of their keys
+pub fn values(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let map = match context.arguments("values", args, 1, &[MAP]) {
+        Some([map, ..]) => map,
+        _ => return variables::Complex::Stack(Vec::new()),
+    };
+    if let variables::Complex::Map(map) = &*map.read().unwrap() {
+        variables::Complex::Stack(map.values().cloned().collect())
+    } else {
+        variables::Complex::Stack(Vec::new())
+    }
+}
+/// Whether the map contains `key`
+pub fn has(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (map, key_value) = match context.arguments("has", args, 2, &[MAP]) {
+        Some([map, key_value, ..]) => (map, key_value),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(variables::Primitive::Bool(
+        if let variables::Complex::Map(map) = &*map.read().unwrap() {
+            map.contains_key(&key(key_value))
+        } else {
+            false
+        },
+    ))
+}
+/// Reference to a function, from `function@script` (or `script::function`) or a string of the same form
+pub fn function_ref(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let reference = match context.arguments("ref", args, 1, &[FUNCTION_OR_STRING]) {
+        Some([reference, ..]) => reference,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    let reference = match &*reference.read().unwrap() {
+        variables::Complex::Primitive(variables::Primitive::Function(script, function)) => {
+            Some((script.to_string(), function.to_string()))
+        }
+        variables::Complex::Primitive(variables::Primitive::Literal(reference)) => {
+            variables::function_reference(reference)
+        }
+        _ => None,
+    };
+    variables::Complex::Primitive(match reference {
+        Some((script, function)) => variables::Primitive::Function(script, function),
+        None => {
+            context.signal(exec::Signal::Fail(String::from(
+                "Function `ref` expects a function, such as function@script or script::function",
+            )));
+            variables::Primitive::Nil
+        }
+    })
+}
+/// Call a function reference with the rest of the arguments, and return its result
+pub fn call(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (reference, input) = match context.arguments("call", args, 1, &[FUNCTION]) {
+        Some([reference, input @ ..]) => (reference, input),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    if let variables::Complex::Primitive(variables::Primitive::Function(script, function)) =
+        &*reference.read().unwrap()
+    {
+        context.signal(exec::Signal::Call(
+            script.to_string(),
+            function.to_string(),
+            input
+                .iter()
+                .map(|arg| arg.read().unwrap().clone())
+                .collect(),
+        ));
+    }
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Sum of two numbers. The result is a float if either of them is.
+pub fn add(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (a, b) = match context.arguments("add", args, 2, &[NUMBER, NUMBER]) {
+        Some([a, b, ..]) => (a, b),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match (&*a.read().unwrap(), &*b.read().unwrap()) {
+        (
+            variables::Complex::Primitive(variables::Primitive::Int(a)),
+            variables::Complex::Primitive(variables::Primitive::Int(b)),
+        ) => variables::Primitive::Int(a.wrapping_add(*b)),
+        (variables::Complex::Primitive(a), variables::Complex::Primitive(b)) => {
+            match (float(a), float(b)) {
+                (Some(a), Some(b)) => variables::Primitive::Float(a + b),
+                _ => variables::Primitive::Nil,
+            }
+        }
+        _ => variables::Primitive::Nil,
+    })
+}
+/// Two stacks joined together, or two values joined as text
+pub fn concat(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (a, b) = match context.arguments("concat", args, 2, &[]) {
+        Some([a, b, ..]) => (a, b),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    match (&*a.read().unwrap(), &*b.read().unwrap()) {
+        (variables::Complex::Stack(a), variables::Complex::Stack(b)) => {
+            variables::Complex::Stack(a.iter().chain(b).cloned().collect())
+        }
+        (a, b) => {
+            variables::Complex::Primitive(variables::Primitive::Literal(format!("{}{}", a, b)))
+        }
+    }
+}
+/// Number of elements of a stack or a map, or number of characters of a string
+pub fn len(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let value = match context.arguments("len", args, 1, &[SIZED]) {
+        Some([value, ..]) => value,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match &*value.read().unwrap() {
+        variables::Complex::Stack(stack) => variables::Primitive::Int(stack.len() as i64),
+        variables::Complex::Map(map) => variables::Primitive::Int(map.len() as i64),
+        variables::Complex::Primitive(variables::Primitive::Literal(text)) => {
+            variables::Primitive::Int(text.chars().count() as i64)
+        }
+        _ => variables::Primitive::Nil,
+    })
+}
+/// Combine the elements of a stack with a builtin (by name) or a function reference
+///
+/// Elements are combined from the first to the last, starting from `initial`
+/// (or the first element if it's omitted), so the result doesn't depend on timing.
+/// Returns `Nil` for an empty stack without `initial`.
+pub fn reduce(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (stack, operation, initial) =
+        match context.arguments("reduce", args, 2, &[STACK, FUNCTION_OR_STRING]) {
+            Some([stack, operation, initial @ ..]) => (stack, operation, initial.first()),
+            _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+        };
+    let operation = match &*operation.read().unwrap() {
+        variables::Complex::Primitive(variables::Primitive::Function(script, function)) => {
+            structures::Operation::External(script.to_string(), function.to_string())
+        }
+        variables::Complex::Primitive(variables::Primitive::Literal(builtin)) => {
+            structures::Operation::Builtin(builtin.to_string())
+        }
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    if let variables::Complex::Stack(stack) = &*stack.read().unwrap() {
+        let mut items = stack.clone();
+        let initial = match initial {
+            Some(initial) => initial.read().unwrap().clone(),
+            None if items.is_empty() => {
+                return variables::Complex::Primitive(variables::Primitive::Nil)
+            }
+            None => items.remove(0),
+        };
+        context.signal(exec::Signal::Reduce(operation, initial, items));
+    }
+    variables::Complex::Primitive(variables::Primitive::Nil)
+}
+/// Base64 of bytes (or of the UTF-8 bytes of a string)
+pub fn base64_encode(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let content = match context.arguments("base64_encode", args, 1, &[BYTES_OR_STRING]) {
+        Some([content, ..]) => content,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match bytes(content) {
+        Some(content) => variables::Primitive::Literal(base64::encode(&content)),
+        None => variables::Primitive::Nil,
+    })
+}
+/// Bytes of a base64 string
+pub fn base64_decode(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let content = match context.arguments("base64_decode", args, 1, &[STRING]) {
+        Some([content, ..]) => content,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(
+        if let variables::Complex::Primitive(variables::Primitive::Literal(content)) =
+            &*content.read().unwrap()
+        {
+            match base64::decode(content) {
+                Ok(content) => variables::Primitive::Bytes(content),
+                Err(e) => {
+                    log::error!("Unable to decode base64: {}", e);
+                    variables::Primitive::Nil
+                }
+            }
+        } else {
+            variables::Primitive::Nil
+        },
+    )
+}
+/// Lowercase hex of bytes (or of the UTF-8 bytes of a string)
+pub fn hex_encode(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let content = match context.arguments("hex_encode", args, 1, &[BYTES_OR_STRING]) {
+        Some([content, ..]) => content,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match bytes(content) {
+        Some(content) => variables::Primitive::Literal(
+            content.iter().map(|byte| format!("{:02x}", byte)).collect(),
+        ),
+        None => variables::Primitive::Nil,
+    })
+}
+/// Bytes of a hex string
+pub fn hex_decode(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let content = match context.arguments("hex_decode", args, 1, &[STRING]) {
+        Some([content, ..]) => content,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(
+        if let variables::Complex::Primitive(variables::Primitive::Literal(content)) =
+            &*content.read().unwrap()
+        {
+            let content = content.as_bytes();
+            match content
+                .chunks(2)
+                .map(|pair| {
+                    std::str::from_utf8(pair)
+                        .ok()
+                        .filter(|pair| pair.len() == 2)
+                        .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                })
+                .collect::<Option<Vec<u8>>>()
+            {
+                Some(content) => variables::Primitive::Bytes(content),
+                None => {
+                    log::error!("Unable to decode hex");
+                    variables::Primitive::Nil
+                }
+            }
+        } else {
+            variables::Primitive::Nil
+        },
+    )
+}
+/// Number of bytes
+pub fn bytes_len(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let content = match context.arguments("bytes_len", args, 1, &[BYTES_OR_STRING]) {
+        Some([content, ..]) => content,
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match bytes(content) {
+        Some(content) => variables::Primitive::Int(content.len() as i64),
+        None => variables::Primitive::Nil,
+    })
+}
+/// Part of bytes, a stack or a string, from `start` until `end` (exclusive, the end by default)
+pub fn slice(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (value, start, end) =
+        match context.arguments("slice", args, 2, &[SLICEABLE, INTEGER, INTEGER]) {
+            Some([value, start, end @ ..]) => (value, start, end.first()),
+            _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+        };
+    let range = |len: usize| {
+        let start = integer(start)?;
+        let end = match end {
+            Some(end) => integer(end)?,
+            None => len as i64,
+        };
+        let start = start.max(0).min(len as i64) as usize;
+        let end = end.max(0).min(len as i64) as usize;
+        Some(start..end.max(start))
+    };
+    let result = match &*value.read().unwrap() {
+        variables::Complex::Primitive(variables::Primitive::Bytes(content)) => range(content.len())
+            .map(|range| {
+                variables::Complex::Primitive(variables::Primitive::Bytes(content[range].to_vec()))
+            }),
+        variables::Complex::Primitive(variables::Primitive::Literal(content)) => {
+            range(content.chars().count()).map(|range| {
+                variables::Complex::Primitive(variables::Primitive::Literal(
+                    content
+                        .chars()
+                        .skip(range.start)
+                        .take(range.len())
+                        .collect(),
+                ))
+            })
+        }
+        variables::Complex::Stack(stack) => {
+            range(stack.len()).map(|range| variables::Complex::Stack(stack[range].to_vec()))
+        }
+        _ => None,
+    };
+    result.unwrap_or(variables::Complex::Primitive(variables::Primitive::Nil))
+}
+/// Content of a file as a string
+pub fn read_file(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let path = match context.arguments("read_file", args, 1, &[STRING]) {
+        Some([path, ..]) => path.read().unwrap().to_string(),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match std::fs::read_to_string(&path) {
+        Ok(content) => variables::Primitive::Literal(content),
+        Err(e) => {
+            log::error!("Unable to read {}: {}", path, e);
+            variables::Primitive::Nil
+        }
+    })
+}
+/// Content of a file as bytes
+pub fn read_bytes(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let path = match context.arguments("read_bytes", args, 1, &[STRING]) {
+        Some([path, ..]) => path.read().unwrap().to_string(),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    variables::Complex::Primitive(match std::fs::read(&path) {
+        Ok(content) => variables::Primitive::Bytes(content),
+        Err(e) => {
+            log::error!("Unable to read {}: {}", path, e);
+            variables::Primitive::Nil
+        }
+    })
+}
+/// Write a value to a file. Bytes are written as they are, everything else as text.
+///
+/// Returns whether the file has been written
+pub fn write_file(
+    args: &[Arc<RwLock<variables::Complex>>],
+    context: &exec::Context,
+) -> variables::Complex {
+    let (path, content) = match context.arguments("write_file", args, 2, &[STRING]) {
+        Some([path, content, ..]) => (path.read().unwrap().to_string(), content),
+        _ => return variables::Complex::Primitive(variables::Primitive::Nil),
+    };
+    let result = match &*content.read().unwrap() {
+        variables::Complex::Primitive(variables::Primitive::Bytes(content)) => {
+            std::fs::write(&path, content)
+        }
+        content => std::fs::write(&path, content.to_string()),
+    };
+    variables::Complex::Primitive(variables::Primitive::Bool(match result {
+        Ok(_) => true,
+        Err(e) => {
+            log::error!("Unable to write {}: {}", path, e);
+            false
+        }
+    }))
+}
+/// Map key of a value. Non-string values use their text form.
+fn key(arg: &Arc<RwLock<variables::Complex>>) -> String {
+    arg.read().unwrap().to_string()
+}
+/// Failure message given to an assertion, or `default` if there is none
+fn reason(arg: Option<&Arc<RwLock<variables::Complex>>>, default: &str) -> String {
+    match arg {
+        Some(reason) => reason.read().unwrap().to_string(),
+        None => default.to_string(),
+    }
+}
+/// Bytes of a value, or the UTF-8 bytes of a string
+fn bytes(arg: &Arc<RwLock<variables::Complex>>) -> Option<Vec<u8>> {
+    match &*arg.read().unwrap() {
+        variables::Complex::Primitive(variables::Primitive::Bytes(content)) => {
+            Some(content.clone())
+        }
+        variables::Complex::Primitive(variables::Primitive::Literal(content)) => {
+            Some(content.as_bytes().to_vec())
+        }
+        _ => None,
+    }
+}
+fn integer(arg: &Arc<RwLock<variables::Complex>>) -> Option<i64> {
+    if let variables::Complex::Primitive(primitive) = &*arg.read().unwrap() {
+        primitive.as_integer()
+    } else {
+        None
+    }
+}
+fn float(primitive: &variables::Primitive) -> Option<f64> {
+    match primitive {
+        variables::Primitive::Int(i) => Some(*i as f64),
+        variables::Primitive::Float(i) => Some(*i),
+        _ => None,
+    }
+}
+fn milliseconds(arg: &Arc<RwLock<variables::Complex>>) -> Option<u64> {
+    integer(arg).filter(|i| *i >= 0).map(|i| i as u64)
+}
+/// Accepts an integer argument
+const INTEGER: exec::Accept = exec::Accept::Integer;
+/// Accepts a string argument
+const STRING: exec::Accept = exec::Accept::Types(&[variables::Type::String]);
+/// Accepts a boolean argument
+const BOOL: exec::Accept = exec::Accept::Types(&[variables::Type::Bool]);
+/// Accepts a stack argument
+const STACK: exec::Accept = exec::Accept::Types(&[variables::Type::Stack]);
+/// Accepts a map argument
+const MAP: exec::Accept = exec::Accept::Types(&[variables::Type::Map]);
+/// Accepts a function reference argument
+const FUNCTION: exec::Accept = exec::Accept::Types(&[variables::Type::Function]);
+/// Accepts a function reference, or a string naming a function
+const FUNCTION_OR_STRING: exec::Accept =
+    exec::Accept::Types(&[variables::Type::Function, variables::Type::String]);
+/// Accepts a number argument
+const NUMBER: exec::Accept = exec::Accept::Types(&[variables::Type::Int, variables::Type::Float]);
+/// Accepts an argument that has a length
+const SIZED: exec::Accept = exec::Accept::Types(&[
+    variables::Type::Stack,
+    variables::Type::Map,
+    variables::Type::String,
+]);
+/// Accepts bytes, or a string as its UTF-8 bytes
+const BYTES_OR_STRING: exec::Accept =
+    exec::Accept::Types(&[variables::Type::Bytes, variables::Type::String]);
+/// Accepts an argument that `slice` can take a part of
+const SLICEABLE: exec::Accept = exec::Accept::Types(&[
+    variables::Type::Bytes,
+    variables::Type::String,
+    variables::Type::Stack,
+]);
+/// Accepts a map or a stack argument
+const COLLECTION: exec::Accept =
+    exec::Accept::Types(&[variables::Type::Map, variables::Type::Stack]);
+fn trim_line_ending(line: &mut String) {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+}
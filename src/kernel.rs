@@ -1,7 +1,7 @@
-use super::{exec, structures, variables};
+use super::{clock, debug, exec, structures, variables};
 use rand::Rng;
-use std::collections::HashMap;
-use std::sync::{mpsc, Arc, RwLock};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
 use std::thread;
 const PACKAGE_IDENTIFIER: usize = 8;
 const THREAD_MAX_NUM: usize = 8;
@@ -19,18 +19,238 @@ impl std::fmt::Display for ExpressionPackage {
         )
     }
 }
+type VariableMap = Arc<Scope>;
+/// Variables of a function call or a block instance
+///
+/// A block instance only stores what it assigns (and `this`), and looks up everything else in
+/// the scope of the package that created the block. That package waits for the block to finish,
+/// so its scope doesn't change while it's shared.
+#[derive(Debug)]
+struct Scope {
+    variable: RwLock<HashMap<String, Arc<RwLock<variables::Complex>>>>,
+    parent: Option<Arc<Scope>>,
+}
+impl Scope {
+    fn new(
+        variable: HashMap<String, Arc<RwLock<variables::Complex>>>,
+        parent: Option<Arc<Scope>>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            variable: RwLock::new(variable),
+            parent,
+        })
+    }
+    fn get(&self, variable_name: &str) -> Option<Arc<RwLock<variables::Complex>>> {
+        match self.variable.read().unwrap().get(variable_name) {
+            Some(variable_content) => Some(Arc::clone(variable_content)),
+            None => self.parent.as_ref()?.get(variable_name),
+        }
+    }
+    /// Every variable visible in this scope, including those of parent scopes
+    fn visible(&self) -> BTreeMap<String, variables::Complex> {
+        let mut result = match &self.parent {
+            Some(parent) => parent.visible(),
+            None => BTreeMap::new(),
+        };
+        for (variable_name, variable_content) in self.variable.read().unwrap().iter() {
+            result.insert(
+                variable_name.to_string(),
+                variable_content.read().unwrap().clone(),
+            );
+        }
+        result
+    }
+    /// Assign a variable in this scope, hiding any variable of the same name in parent scopes
+    fn insert(&self, variable_name: &str, variable_content: variables::Complex) {
+        self.variable.write().unwrap().insert(
+            variable_name.to_string(),
+            Arc::new(RwLock::new(variable_content)),
+        );
+    }
+}
 #[derive(Debug)]
 pub struct ExpressionPackage {
+    /// Also used as the seed of packages derived from this one
     identifier: [u8; PACKAGE_IDENTIFIER],
     expression: Arc<RwLock<structures::Expression>>,
-    variable: Arc<RwLock<HashMap<String, Arc<RwLock<variables::Primitive>>>>>,
+    variable: VariableMap,
+    /// Function or block `expression` belongs to
+    body: Arc<Vec<Arc<RwLock<structures::Expression>>>>,
+    /// Position of `expression` inside `body`
+    position: usize,
+    /// What should happen after the last expression of `body`
+    then: Option<Box<Resume>>,
+    output: exec::Output,
+}
+/// Continuation of a finished function or block
+#[derive(Debug)]
+enum Resume {
+    /// Function called by `caller` returns
+    Return {
+        returns: Returns,
+        caller: ExpressionPackage,
+    },
+    /// The `usize`th instance of a `=>` block finishes
+    Join(Arc<Join>, usize),
+    /// Function called by `caller` in `reduce` returns, and its result is combined with the next
+    /// element of `rest` (from the end), or becomes the result of `reduce`
+    Fold {
+        returns: Returns,
+        script: structures::Script,
+        function: String,
+        rest: Vec<variables::Complex>,
+        caller: ExpressionPackage,
+    },
+    /// Entry function finishes, and its result is sent back
+    Report {
+        returns: Returns,
+        sender: mpsc::Sender<variables::Complex>,
+    },
+    /// Input of a REPL session finishes, and every variable of the session is sent back
+    Session(mpsc::Sender<HashMap<String, variables::Complex>>),
+}
+/// Result of a function, as declared by its signature
+#[derive(Debug)]
+struct Returns {
+    function: String,
+    output: Option<String>,
+    output_type: Option<variables::Type>,
+}
+impl Returns {
+    fn of(function: &structures::Function) -> Self {
+        Self {
+            function: function.name.to_string(),
+            output: function.output.clone(),
+            output_type: function.output_type,
+        }
+    }
+    /// The variable `output` (if any) of a finished function
+    ///
+    /// Fails if it doesn't match the annotation of the result
+    fn result(&self, variable: &VariableMap) -> Result<variables::Complex, String> {
+        let result = ExpressionPackage::function_result(&self.output, variable);
+        if let (Some(output), Some(output_type)) = (&self.output, self.output_type) {
+            let result_type = variables::Type::of(&result);
+            if result_type != output_type {
+                return Err(format!(
+                    "Result `{}` of function `{}` expects {}, found {}",
+                    output, self.function, output_type, result_type
+                ));
+            }
+        }
+        Ok(result)
+    }
+}
+/// Instances of a `=>` block that are still running
+#[derive(Debug)]
+struct Join {
+    pending: Mutex<Pending>,
+    output: Option<Arc<exec::OrderedOutput>>,
+    /// Variable collected from every instance, if the block is a collect block
+    result: Option<String>,
+}
+#[derive(Debug)]
+struct Pending {
+    remaining: usize,
+    /// Package that created the block. Continued once every instance has finished.
+    caller: Option<ExpressionPackage>,
+    /// Collected variables, in the order of the instances
+    results: Vec<variables::Complex>,
+}
+/// Identifier of a package derived from `identifier`
+///
+/// Identifiers only depend on where a package comes from, not on the order threads run in,
+/// so a seeded kernel generates the same identifiers (and random numbers) on every run.
+fn derive_identifier(identifier: [u8; PACKAGE_IDENTIFIER], salt: u64) -> [u8; PACKAGE_IDENTIFIER] {
+    // SplitMix64
+    let mut z = u64::from_le_bytes(identifier)
+        .wrapping_add(salt.wrapping_add(1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    (z ^ (z >> 31)).to_le_bytes()
 }
 impl ExpressionPackage {
+    /// Packages of a function, and a receiver for the result of the function
+    ///
+    /// Fails if the input doesn't match the parameters of the function
     pub fn from_function(
         function: &structures::Function,
-        mut input: Vec<variables::Primitive>,
-    ) -> Vec<Self> {
-        let mut self_vector = Vec::new();
+        input: Vec<variables::Complex>,
+    ) -> Result<(Vec<Self>, mpsc::Receiver<variables::Complex>), String> {
+        Self::from_function_with(function, input, exec::Output::Stdout)
+    }
+    fn from_function_with(
+        function: &structures::Function,
+        input: Vec<variables::Complex>,
+        output: exec::Output,
+    ) -> Result<(Vec<Self>, mpsc::Receiver<variables::Complex>), String> {
+        function.check_input(&input)?;
+        let (sender, receiver) = mpsc::channel();
+        let variable = Self::function_variable(function, input);
+        if function.process.is_empty() {
+            sender
+                .send(Returns::of(function).result(&variable)?)
+                .unwrap();
+            return Ok((Vec::new(), receiver));
+        }
+        let then = Box::new(Resume::Report {
+            returns: Returns::of(function),
+            sender,
+        });
+        Ok((
+            Self::from_body(
+                Arc::new(function.process.clone()),
+                variable,
+                rand::thread_rng().gen(),
+                Some(then),
+                output,
+            )
+            .into_iter()
+            .collect(),
+            receiver,
+        ))
+    }
+    /// Packages of a REPL input, and a receiver for the variables of the session once it finishes
+    ///
+    /// The input runs with the variables of `session`, so assignments of earlier inputs stay visible.
+    pub fn from_session(
+        body: Vec<Arc<RwLock<structures::Expression>>>,
+        session: HashMap<String, variables::Complex>,
+    ) -> (
+        Vec<Self>,
+        mpsc::Receiver<HashMap<String, variables::Complex>>,
+    ) {
+        let (sender, receiver) = mpsc::channel();
+        if body.is_empty() {
+            sender.send(session).unwrap();
+            return (Vec::new(), receiver);
+        }
+        let variable = Scope::new(
+            session
+                .into_iter()
+                .map(|(variable_name, variable_content)| {
+                    (variable_name, Arc::new(RwLock::new(variable_content)))
+                })
+                .collect(),
+            None,
+        );
+        (
+            Self::from_body(
+                Arc::new(body),
+                variable,
+                rand::thread_rng().gen(),
+                Some(Box::new(Resume::Session(sender))),
+                exec::Output::Stdout,
+            )
+            .into_iter()
+            .collect(),
+            receiver,
+        )
+    }
+    fn function_variable(
+        function: &structures::Function,
+        mut input: Vec<variables::Complex>,
+    ) -> VariableMap {
         //First, find input variables
         let mut variable_hashmap = HashMap::new();
         for variable_name in &function.input {
@@ -39,80 +259,558 @@ impl ExpressionPackage {
                 Arc::new(RwLock::new(input.remove(0))),
             );
         }
-        let arc_variable_hashmap = Arc::new(RwLock::new(variable_hashmap));
-        let mut rng = rand::thread_rng();
-        for expression in &function.process {
-            //Generate a random identifier
-            let mut identifier = [0; PACKAGE_IDENTIFIER];
-            for identifier_byte in identifier.iter_mut() {
-                *identifier_byte = rng.gen();
+        Scope::new(variable_hashmap, None)
+    }
+    /// Package of the first expression in `body`, or `None` if `body` is empty
+    fn from_body(
+        body: Arc<Vec<Arc<RwLock<structures::Expression>>>>,
+        variable: VariableMap,
+        identifier: [u8; PACKAGE_IDENTIFIER],
+        then: Option<Box<Resume>>,
+        output: exec::Output,
+    ) -> Option<Self> {
+        let expression = Arc::clone(body.first()?);
+        Some(Self {
+            identifier,
+            expression,
+            variable,
+            body,
+            position: 0,
+            then,
+            output,
+        })
+    }
+    /// What the debugger shows of the package
+    fn state(
+        &self,
+        locations: &HashMap<usize, debug::Location>,
+        sleeping: bool,
+    ) -> debug::PackageState {
+        debug::PackageState {
+            identifier: self.to_string(),
+            location: locations
+                .get(&(Arc::as_ptr(&self.expression) as usize))
+                .cloned(),
+            expression: self.expression.read().unwrap().to_string(),
+            variables: self.variable.visible(),
+            sleeping,
+        }
+    }
+    /// Execute the expression, and return packages that should be executed next
+    fn execute(
+        self,
+        builtin_hashmap: &HashMap<String, Box<exec::BuiltInFunction>>,
+        script_map: &RwLock<HashMap<String, structures::Script>>,
+        config: &Config,
+    ) -> Message {
+        let expression = Arc::clone(&self.expression);
+        let expression = expression.read().unwrap();
+        //Variable collection
+        let mut variable_vector = Vec::new();
+        for variable_name in &expression.variables {
+            if variable_name.starts_with('"') && variable_name.ends_with('"') {
+                log::info!("Variable {} seems to be a string.", variable_name);
+                variable_vector.push(Arc::new(RwLock::new(variables::Complex::Primitive(
+                    variables::Primitive::Literal(
+                        variable_name[1..variable_name.len() - 1].to_string(),
+                    ),
+                ))));
+            } else if variables::is_number(variable_name) {
+                variable_vector.push(Arc::new(RwLock::new(variables::Complex::Primitive(
+                    variables::Primitive::from_number(variable_name),
+                ))));
+            } else if let Some((script, function)) = variables::function_reference(variable_name) {
+                variable_vector.push(Arc::new(RwLock::new(variables::Complex::Primitive(
+                    variables::Primitive::Function(script, function),
+                ))));
+            } else {
+                match self.variable.get(variable_name) {
+                    Some(variable_content) => variable_vector.push(variable_content),
+                    None => {
+                        return Message::Fail(format!(
+                            "Variable `{}` is not defined",
+                            variable_name
+                        ))
+                    }
+                }
+            }
+        }
+        //Operation
+        match &expression.operation {
+            structures::Operation::Builtin(builtin_command) => {
+                let op_function = match builtin_hashmap.get(builtin_command) {
+                    Some(op_function) => op_function,
+                    None => {
+                        return Message::Fail(format!("Unknown function `{}`", builtin_command))
+                    }
+                };
+                let context = exec::Context::new(
+                    self.output.clone(),
+                    config,
+                    u64::from_le_bytes(self.identifier),
+                );
+                let result = op_function(&variable_vector, &context);
+                match context.take_signal() {
+                    Some(exec::Signal::Exit(code)) => Message::ExitWithCode(code),
+                    Some(exec::Signal::Fail(reason)) => Message::Fail(reason),
+                    Some(exec::Signal::Sleep(duration)) => match self.complete(result, config) {
+                        Ok(package_vector) => {
+                            Message::Sleep(config.clock.now() + duration, package_vector)
+                        }
+                        Err(stop) => Message::completed(Err(stop)),
+                    },
+                    Some(exec::Signal::Call(script_name, function_name, input)) => {
+                        self.call(&script_name, &function_name, input, script_map, config)
+                    }
+                    Some(exec::Signal::Reduce(operation, initial, items)) => match operation {
+                        structures::Operation::Builtin(builtin_command) => {
+                            match builtin_hashmap.get(&builtin_command) {
+                                Some(op_function) => {
+                                    let mut accumulator = initial;
+                                    for item in items {
+                                        accumulator = op_function(
+                                            &[
+                                                Arc::new(RwLock::new(accumulator)),
+                                                Arc::new(RwLock::new(item)),
+                                            ],
+                                            &context,
+                                        );
+                                        // A failing step stops the fold right away
+                                        match context.take_signal() {
+                                            None => (),
+                                            Some(exec::Signal::Exit(code)) => {
+                                                return Message::ExitWithCode(code)
+                                            }
+                                            Some(exec::Signal::Fail(reason)) => {
+                                                return Message::Fail(reason)
+                                            }
+                                            Some(_) => {
+                                                return Message::Fail(format!(
+                                                    "Function `{}` can't be used with `reduce`",
+                                                    builtin_command
+                                                ))
+                                            }
+                                        }
+                                    }
+                                    Message::completed(self.complete(accumulator, config))
+                                }
+                                None => {
+                                    Message::Fail(format!("Unknown function `{}`", builtin_command))
+                                }
+                            }
+                        }
+                        structures::Operation::External(script_name, function_name) => {
+                            let script = script_map.read().unwrap().get(&script_name).cloned();
+                            match script {
+                                Some(script) if script.contains_key(&function_name) => {
+                                    Message::completed(self.fold(
+                                        script,
+                                        function_name,
+                                        initial,
+                                        items.into_iter().rev().collect(),
+                                        config,
+                                    ))
+                                }
+                                _ => Message::Fail(format!(
+                                    "Function `{}@{}` not found",
+                                    function_name, script_name
+                                )),
+                            }
+                        }
+                    },
+                    None => Message::completed(self.complete(result, config)),
+                }
+            }
+            structures::Operation::External(script_name, function_name) => {
+                //Transfowm variable vector
+                let mut new_variable_vec = Vec::new();
+                for variable in variable_vector {
+                    new_variable_vec.push(variable.read().unwrap().clone());
+                }
+                self.call(
+                    script_name,
+                    function_name,
+                    new_variable_vec,
+                    script_map,
+                    config,
+                )
+            }
+        }
+    }
+    /// Start a function, which continues this package with its result once it returns
+    fn call(
+        self,
+        script_name: &str,
+        function_name: &str,
+        input: Vec<variables::Complex>,
+        script_map: &RwLock<HashMap<String, structures::Script>>,
+        config: &Config,
+    ) -> Message {
+        let script = script_map.read().unwrap();
+        let function = match script
+            .get(script_name)
+            .and_then(|script| script.get(function_name))
+        {
+            Some(function) => function,
+            None => {
+                return Message::Fail(format!(
+                    "Function `{}@{}` not found",
+                    function_name, script_name
+                ))
             }
-            self_vector.push(Self {
+        };
+        if let Err(e) = function.check_input(&input) {
+            return Message::Mismatch(e);
+        }
+        let identifier = derive_identifier(self.identifier, 1);
+        let output = self.output.clone();
+        let then = Resume::Return {
+            returns: Returns::of(function),
+            caller: self,
+        };
+        Message::completed(Self::start(
+            function, input, identifier, output, then, config,
+        ))
+    }
+    /// Combine `accumulator` with the last element of `rest` using a script function, until
+    /// `rest` is empty. The accumulator then becomes the result of the expression.
+    fn fold(
+        self,
+        script: structures::Script,
+        function_name: String,
+        accumulator: variables::Complex,
+        mut rest: Vec<variables::Complex>,
+        config: &Config,
+    ) -> Result<Vec<Self>, Stop> {
+        let item = match rest.pop() {
+            Some(item) => item,
+            None => return self.complete(accumulator, config),
+        };
+        let function = script.get(&function_name).unwrap();
+        let input = vec![accumulator, item];
+        function.check_input(&input).map_err(Stop::Mismatch)?;
+        let identifier =
+            derive_identifier(derive_identifier(self.identifier, 1), rest.len() as u64);
+        let output = self.output.clone();
+        let then = Resume::Fold {
+            returns: Returns::of(function),
+            script: Arc::clone(&script),
+            function: function_name.to_string(),
+            rest,
+            caller: self,
+        };
+        Self::start(function, input, identifier, output, then, config)
+    }
+    /// Packages of a function called with `input`, which continue with `then` once it returns
+    fn start(
+        function: &structures::Function,
+        input: Vec<variables::Complex>,
+        identifier: [u8; PACKAGE_IDENTIFIER],
+        output: exec::Output,
+        then: Resume,
+        config: &Config,
+    ) -> Result<Vec<Self>, Stop> {
+        let variable = Self::function_variable(function, input);
+        let then = Some(Box::new(then));
+        if function.process.is_empty() {
+            Self::resume(then, &variable, config)
+        } else {
+            Ok(Self::from_body(
+                Arc::new(function.process.clone()),
+                variable,
                 identifier,
-                expression: Arc::clone(expression),
-                variable: Arc::clone(&arc_variable_hashmap),
-            })
+                then,
+                output,
+            )
+            .into_iter()
+            .collect())
+        }
+    }
+    /// Store the result of the expression, and return packages that should be executed next
+    fn complete(self, result: variables::Complex, config: &Config) -> Result<Vec<Self>, Stop> {
+        let expression = Arc::clone(&self.expression);
+        let to = &expression.read().unwrap().to;
+        match to {
+            structures::ExpressionTo::ToVar(variable_name) => {
+                self.variable.insert(variable_name, result);
+                self.advance(config)
+            }
+            structures::ExpressionTo::ToBlock(block) => self.fan_out(result, block, None, config),
+            structures::ExpressionTo::Collect(_, block_result, block) => {
+                self.fan_out(result, block, Some(block_result.to_string()), config)
+            }
+            structures::ExpressionTo::Nil => self.advance(config),
         }
-        self_vector
-    }
-    pub fn from_expression(
-        expression: std::sync::Arc<std::sync::RwLock<structures::Expression>>,
-        input: HashMap<String, variables::Primitive>,
-    ) -> Self {
-        let mut rng = rand::thread_rng();
-        let mut identifier = [0; PACKAGE_IDENTIFIER];
-        for identifier_byte in identifier.iter_mut() {
-            *identifier_byte = rng.gen();
+    }
+    /// Create an instance of `block` for every element of the result
+    ///
+    /// The package continues once every instance has finished. If `collect` is set, that variable
+    /// of every instance is stored in order, and the package assigns them once it continues.
+    fn fan_out(
+        self,
+        result: variables::Complex,
+        block: &[Arc<RwLock<structures::Expression>>],
+        collect: Option<String>,
+        config: &Config,
+    ) -> Result<Vec<Self>, Stop> {
+        // Elements of the result, with their key if the result is a map
+        let stack_result: Vec<(Option<String>, variables::Complex)> = match result {
+            variables::Complex::Primitive(single_result) => {
+                vec![(None, variables::Complex::Primitive(single_result))]
+            }
+            variables::Complex::Stack(stack_result) => {
+                stack_result.into_iter().map(|i| (None, i)).collect()
+            }
+            variables::Complex::Map(map_result) => map_result
+                .into_iter()
+                .map(|(key, value)| (Some(key), value))
+                .collect(),
+        };
+        if stack_result.is_empty() || block.is_empty() {
+            let results =
+                vec![variables::Complex::Primitive(variables::Primitive::Nil); stack_result.len()];
+            return self.collected(results, config);
         }
-        let mut input_hashmap = HashMap::new();
-        for (variable_name, variable_content) in input {
-            input_hashmap.insert(variable_name, Arc::new(RwLock::new(variable_content)));
+        let block = Arc::new(block.to_vec());
+        let ordered_output = if config.ordered_output {
+            Some(Arc::new(exec::OrderedOutput::new(self.output.clone())))
+        } else {
+            None
+        };
+        let join = Arc::new(Join {
+            pending: Mutex::new(Pending {
+                remaining: stack_result.len(),
+                caller: None,
+                results: vec![
+                    variables::Complex::Primitive(variables::Primitive::Nil);
+                    stack_result.len()
+                ],
+            }),
+            output: ordered_output.clone(),
+            result: collect,
+        });
+        let mut blocks: Vec<Self> = Vec::new();
+        for (index, (key, single_result)) in stack_result.into_iter().enumerate() {
+            let mut variable_hmap = HashMap::new();
+            let single_result = Arc::new(RwLock::new(single_result));
+            if let Some(key) = key {
+                variable_hmap.insert(
+                    String::from("key"),
+                    Arc::new(RwLock::new(variables::Complex::Primitive(
+                        variables::Primitive::Literal(key),
+                    ))),
+                );
+                variable_hmap.insert(String::from("value"), Arc::clone(&single_result));
+            }
+            // Inner collections are bound as they are, so they can be fanned out again
+            variable_hmap.insert(String::from("this"), single_result);
+            let output = match &ordered_output {
+                Some(ordered_output) => exec::Output::Ordered(Arc::clone(ordered_output), index),
+                None => self.output.clone(),
+            };
+            blocks.extend(Self::from_body(
+                Arc::clone(&block),
+                Scope::new(variable_hmap, Some(Arc::clone(&self.variable))),
+                derive_identifier(self.identifier, index as u64 + 2),
+                Some(Box::new(Resume::Join(Arc::clone(&join), index))),
+                output,
+            ));
         }
-        Self {
-            identifier,
-            expression,
-            variable: Arc::new(RwLock::new(input_hashmap)),
+        join.pending.lock().unwrap().caller = Some(self);
+        Ok(blocks)
+    }
+    /// Assign the results of a collect block (if it is one), and move on
+    fn collected(
+        self,
+        results: Vec<variables::Complex>,
+        config: &Config,
+    ) -> Result<Vec<Self>, Stop> {
+        if let structures::ExpressionTo::Collect(variable_name, ..) =
+            &self.expression.read().unwrap().to
+        {
+            self.variable
+                .insert(variable_name, variables::Complex::Stack(results));
+        }
+        self.advance(config)
+    }
+    /// Move on to the next expression of the body, or resume whatever is waiting for the body
+    fn advance(self, config: &Config) -> Result<Vec<Self>, Stop> {
+        let position = self.position + 1;
+        if let Some(expression) = self.body.get(position) {
+            return Ok(vec![Self {
+                identifier: derive_identifier(self.identifier, 0),
+                expression: Arc::clone(expression),
+                variable: self.variable,
+                body: self.body,
+                position,
+                then: self.then,
+                output: self.output,
+            }]);
         }
+        Self::resume(self.then, &self.variable, config)
     }
-    pub fn from_vec_expression(
-        expression_vec: Vec<std::sync::Arc<std::sync::RwLock<structures::Expression>>>,
-        input: HashMap<String, variables::Primitive>,
-    ) -> Vec<Self> {
-        let mut self_vector = Vec::new();
-        let mut rng = rand::thread_rng();
-        let mut input_hashmap = HashMap::new();
-        for (variable_name, variable_content) in input {
-            input_hashmap.insert(variable_name, Arc::new(RwLock::new(variable_content)));
+    fn function_result(output: &Option<String>, variable: &VariableMap) -> variables::Complex {
+        match output {
+            Some(variable_name) => match variable.get(variable_name) {
+                Some(variable_content) => variable_content.read().unwrap().clone(),
+                None => {
+                    log::warn!("Result variable {} is never assigned", variable_name);
+                    variables::Complex::Primitive(variables::Primitive::Nil)
+                }
+            },
+            None => variables::Complex::Primitive(variables::Primitive::Nil),
         }
-        let arc_variable_hashmap = Arc::new(RwLock::new(input_hashmap));
-        for expression in expression_vec {
-            let mut identifier = [0; PACKAGE_IDENTIFIER];
-            for identifier_byte in identifier.iter_mut() {
-                *identifier_byte = rng.gen();
+    }
+    /// Continue whatever is waiting for a body that has finished with `variable`
+    ///
+    /// Fails if the result of a function doesn't match its annotation
+    fn resume(
+        then: Option<Box<Resume>>,
+        variable: &VariableMap,
+        config: &Config,
+    ) -> Result<Vec<Self>, Stop> {
+        match then.map(|then| *then) {
+            None => Ok(Vec::new()),
+            Some(Resume::Return { returns, caller }) => {
+                caller.complete(returns.result(variable).map_err(Stop::Mismatch)?, config)
+            }
+            Some(Resume::Fold {
+                returns,
+                script,
+                function,
+                rest,
+                caller,
+            }) => caller.fold(
+                script,
+                function,
+                returns.result(variable).map_err(Stop::Mismatch)?,
+                rest,
+                config,
+            ),
+            Some(Resume::Report { returns, sender }) => {
+                let result = returns.result(variable).map_err(Stop::Mismatch)?;
+                if sender.send(result).is_err() {
+                    log::warn!("Result of the entry function is no longer expected");
+                }
+                Ok(Vec::new())
+            }
+            Some(Resume::Session(sender)) => {
+                let session = variable
+                    .variable
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|(variable_name, variable_content)| {
+                        (
+                            variable_name.to_string(),
+                            variable_content.read().unwrap().clone(),
+                        )
+                    })
+                    .collect();
+                if sender.send(session).is_err() {
+                    log::warn!("Variables of the session are no longer expected");
+                }
+                Ok(Vec::new())
+            }
+            Some(Resume::Join(join, index)) => {
+                if let Some(ordered_output) = &join.output {
+                    ordered_output.finish(index);
+                }
+                let mut pending = join.pending.lock().unwrap();
+                if join.result.is_some() {
+                    pending.results[index] = Self::function_result(&join.result, variable);
+                }
+                pending.remaining -= 1;
+                if pending.remaining == 0 {
+                    let caller = pending.caller.take().unwrap();
+                    let results = std::mem::take(&mut pending.results);
+                    drop(pending);
+                    caller.collected(results, config)
+                } else {
+                    Ok(Vec::new())
+                }
             }
-            self_vector.push(Self {
-                identifier,
-                expression,
-                variable: Arc::clone(&arc_variable_hashmap),
-            })
         }
-        self_vector
+    }
+}
+/// Options that change how the kernel executes packages
+#[derive(Debug)]
+pub struct Config {
+    /// Buffer output of `=>` block instances, so that it appears in the order of the stack
+    pub ordered_output: bool,
+    /// Command line arguments of the script, returned by `args()`
+    pub arguments: Vec<String>,
+    /// Time source of `now`, `sleep` and `elapsed`
+    pub clock: Arc<dyn clock::Clock>,
+    /// Seed of identifiers and random builtins. Random on every run if `None`
+    pub seed: Option<u64>,
+    /// Start paused, and only execute packages when the debugger asks to (see `Kernel::debug`)
+    pub debug: bool,
+    /// Keep running when a package fails, and drop every other package instead (see
+    /// `Kernel::failure`). Packages should then only be sent once the earlier ones have finished.
+    pub recover: bool,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ordered_output: false,
+            arguments: Vec::new(),
+            clock: Arc::new(clock::SystemClock),
+            seed: None,
+            debug: false,
+            recover: false,
+        }
     }
 }
 pub enum Message {
-    Query([u8; PACKAGE_IDENTIFIER]),
     Package(ExpressionPackage),
-    QueryResult(QueryResult),
     NewScript(String, structures::Script),
+    /// Register a script, replacing any script of the same name
+    ReplaceScript(String, structures::Script),
     Complete,
     CompleteWithPackage(Vec<ExpressionPackage>),
+    /// A script called `exit(code)`
+    ExitWithCode(i32),
+    /// A package failed, and the kernel should stop
+    Fail(String),
+    /// Arguments or the result of a function don't match its signature
+    Mismatch(String),
+    /// Packages that should only be assigned once the clock reaches the time
+    Sleep(u64, Vec<ExpressionPackage>),
     PackageReceived,
     Exit,
     ExitGracefully,
+    /// Pause before the expressions of the breakpoints, replacing earlier breakpoints
+    Break(Vec<debug::Breakpoint>),
+    /// Execute the next package while paused, and pause again once it has finished
+    Step,
+    /// Execute packages until one reaches a breakpoint
+    Continue,
+    /// Report the packages waiting while paused
+    Inspect,
+    /// The kernel paused, with the packages waiting to be executed (in the order they would be)
+    Paused(Vec<debug::PackageState>),
+}
+impl Message {
+    fn completed(result: Result<Vec<ExpressionPackage>, Stop>) -> Self {
+        match result {
+            Ok(package_vector) if package_vector.is_empty() => Message::Complete,
+            Ok(package_vector) => Message::CompleteWithPackage(package_vector),
+            Err(Stop::Exit(code)) => Message::ExitWithCode(code),
+            Err(Stop::Fail(reason)) => Message::Fail(reason),
+            Err(Stop::Mismatch(reason)) => Message::Mismatch(reason),
+        }
+    }
 }
-pub enum QueryResult {
-    Running,
-    Completed,
+/// Why the kernel stopped before every package was executed
+#[derive(Debug, PartialEq)]
+pub enum Stop {
+    /// A script called `exit(code)`
+    Exit(i32),
+    /// A package failed
+    Fail(String),
+    /// Arguments or the result of a function don't match its signature
+    Mismatch(String),
 }
 enum ThreadState {
     Idle,
@@ -121,7 +819,17 @@ enum ThreadState {
 pub struct Kernel {
     sender: mpsc::Sender<Message>,
     receiver: mpsc::Receiver<Message>,
-    handle: thread::JoinHandle<()>,
+    handle: thread::JoinHandle<Option<Stop>>,
+}
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    match panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+    {
+        Some(message) => format!("Package panicked: {}", message),
+        None => String::from("Package panicked"),
+    }
 }
 impl std::fmt::Display for Thread {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -143,10 +851,16 @@ struct Thread {
     state: ThreadState,
     handle: thread::JoinHandle<()>,
 }
+impl Default for Kernel {
+    fn default() -> Self {
+        Self::with_config(Config::default())
+    }
+}
 impl Kernel {
-    pub fn new() -> Self {
+    pub fn with_config(config: Config) -> Self {
         let (sender, rx) = mpsc::channel();
         let (tx, receiver) = mpsc::channel();
+        let config = Arc::new(config);
         log::trace!("Kernel created");
         let handle = thread::Builder::new()
             .name("Kernel".to_string())
@@ -156,24 +870,56 @@ impl Kernel {
                 tx.send(Message::Complete).unwrap();
                 let script_hashmap = Arc::new(RwLock::new(HashMap::new()));
                 let mut thread_vec: Vec<Thread> = Vec::new();
-                let mut assigned_job_identifier_hashmap: HashMap<[u8; THREAD_IDENTIFIER_LENGTH], [u8; PACKAGE_IDENTIFIER]> = HashMap::new();
-                let mut completed_job_identifier_list: Vec<[u8; PACKAGE_IDENTIFIER]> = Vec::new();
+                let mut assigned_job_identifier_hashmap: HashMap<
+                    [u8; THREAD_IDENTIFIER_LENGTH],
+                    [u8; PACKAGE_IDENTIFIER],
+                > = HashMap::new();
                 let mut assign_queue = Vec::new();
+                let mut stop_reason = None;
+                // Threads executing a package of a run that failed, whose results are dropped
+                let mut discarded = HashSet::new();
+                let mut timer_vec: Vec<(u64, Vec<ExpressionPackage>)> = Vec::new();
+                let mut rng: rand::rngs::StdRng = match config.seed {
+                    Some(seed) => rand::SeedableRng::seed_from_u64(seed),
+                    None => rand::SeedableRng::from_entropy(),
+                };
+                // Debugger state. Packages are only assigned while paused if a step was asked for.
+                let mut paused = config.debug;
+                let mut step = false;
+                // Whether the debugger waits for the packages, once every thread is idle
+                let mut report = false;
+                let mut breakpoints: Vec<debug::Breakpoint> = Vec::new();
+                let mut locations = HashMap::new();
+                // Package that runs past its breakpoint, once the debugger continues
+                let mut released = None;
                 loop {
                     let message_from_main_thread_warped = rx.try_recv();
                     if let Ok(message_from_main_thread) = message_from_main_thread_warped {
                         match message_from_main_thread {
                             Message::NewScript(script_name, script) => {
-                                if script_hashmap
-                                    .write()
-                                    .unwrap()
-                                    .insert(script_name, script)
-                                    .is_some()
-                                {
-                                    log::warn!("Script already registered. Rewritting...");
-                                };
+                                if config.debug {
+                                    debug::index(&script_name, &script, &mut locations);
+                                }
+                                match script_hashmap.write().unwrap().entry(script_name) {
+                                    std::collections::hash_map::Entry::Occupied(entry) => {
+                                        log::error!(
+                                            "Module {} is already registered. The new one is ignored.",
+                                            entry.key()
+                                        );
+                                    }
+                                    std::collections::hash_map::Entry::Vacant(entry) => {
+                                        entry.insert(script);
+                                    }
+                                }
                             }
-                            Message::Package(assign_package) => {
+                            Message::ReplaceScript(script_name, script) => {
+                                if config.debug {
+                                    debug::index(&script_name, &script, &mut locations);
+                                }
+                                script_hashmap.write().unwrap().insert(script_name, script);
+                            }
+                            Message::Package(mut assign_package) => {
+                                assign_package.identifier = rng.gen();
                                 log::trace!("Kernel received package {}", assign_package);
                                 assign_queue.push(assign_package);
                                 tx.send(Message::PackageReceived).unwrap();
@@ -185,57 +931,115 @@ impl Kernel {
                                     thread.handle.join().unwrap();
                                 }
                                 log::info!("Kernel stopped.");
-                                return;
+                                return None;
                             }
                             Message::ExitGracefully => now_stop = true,
+                            Message::Break(new_breakpoints) => breakpoints = new_breakpoints,
+                            Message::Step => step = true,
+                            Message::Continue => {
+                                paused = false;
+                                released = assign_queue
+                                    .first()
+                                    .map(|package: &ExpressionPackage| package.identifier);
+                            }
+                            Message::Inspect => report = true,
                             _ => log::error!("Kernel received an unsupported message"),
                         };
                     } else if let Err(std::sync::mpsc::TryRecvError::Disconnected) =
                         message_from_main_thread_warped
                     {
                         log::error!("Kernel will stop ungracefully.");
-                        return;
+                        return None;
                     }
                     //Refresh states for threads
                     for thread in &mut thread_vec {
                         let message_from_thread_warped = thread.receiver.try_recv();
                         if let Ok(message_from_thread) = message_from_thread_warped {
+                            if discarded.remove(&thread.identifier) {
+                                log::trace!("Dropping what thread {} returned", thread);
+                                thread.state = ThreadState::Idle;
+                                assigned_job_identifier_hashmap.remove(&thread.identifier);
+                                continue;
+                            }
                             match message_from_thread {
                                 Message::Complete => {
                                     thread.state = ThreadState::Idle;
-                                    log::trace!("Pushing job identifier from thread identifier {}", thread);
-                                    if assigned_job_identifier_hashmap.get(&thread.identifier).is_some() {
-                                        completed_job_identifier_list.push(assigned_job_identifier_hashmap.remove(&thread.identifier).unwrap());
-                                    } else {
+                                    log::trace!(
+                                        "Removing job identifier from thread identifier {}",
+                                        thread
+                                    );
+                                    if assigned_job_identifier_hashmap
+                                        .remove(&thread.identifier)
+                                        .is_none()
+                                    {
                                         log::warn!("Job identifier seems not inside job list");
                                     }
-                                },
+                                }
                                 Message::CompleteWithPackage(package_vector) => {
                                     for assign_package in package_vector {
                                         assign_queue.push(assign_package);
                                     }
                                     thread.state = ThreadState::Idle;
-                                    completed_job_identifier_list.push(assigned_job_identifier_hashmap.remove(&thread.identifier).unwrap());
+                                    assigned_job_identifier_hashmap.remove(&thread.identifier);
                                 }
-                                Message::Package(package) => {
-                                    log::trace!("Received package {} from thread {}", package, thread);
-                                    assign_queue.push(package);
-                                    thread.sender.send(Message::PackageReceived).unwrap();
+                                Message::ExitWithCode(code) => {
+                                    log::trace!(
+                                        "Thread {} requested exit with code {}",
+                                        thread,
+                                        code
+                                    );
+                                    thread.state = ThreadState::Idle;
+                                    assigned_job_identifier_hashmap.remove(&thread.identifier);
+                                    stop_reason.get_or_insert(Stop::Exit(code));
                                 }
-                                Message::Query(package_id) => {
-                                    log::trace!("Received query request from thread {}", thread);
-                                    let mut is_completed = Message::QueryResult(QueryResult::Running);
-                                    for i in &completed_job_identifier_list {
-                                        if &package_id == i {
-                                            is_completed = Message::QueryResult(QueryResult::Completed);
-                                        }
-                                    }
-                                    thread.sender.send(is_completed).unwrap();
+                                Message::Fail(reason) => {
+                                    log::trace!("Thread {} failed: {}", thread, reason);
+                                    thread.state = ThreadState::Idle;
+                                    assigned_job_identifier_hashmap.remove(&thread.identifier);
+                                    stop_reason.get_or_insert(Stop::Fail(reason));
+                                }
+                                Message::Mismatch(reason) => {
+                                    log::trace!("Thread {} failed: {}", thread, reason);
+                                    thread.state = ThreadState::Idle;
+                                    assigned_job_identifier_hashmap.remove(&thread.identifier);
+                                    stop_reason.get_or_insert(Stop::Mismatch(reason));
+                                }
+                                Message::Sleep(time, package_vector) => {
+                                    thread.state = ThreadState::Idle;
+                                    assigned_job_identifier_hashmap.remove(&thread.identifier);
+                                    let position = timer_vec
+                                        .partition_point(|(timer_time, _)| *timer_time <= time);
+                                    timer_vec.insert(position, (time, package_vector));
                                 }
                                 _ => log::error!("Kernel received an unsupported message"),
                             }
                         }
                     }
+                    if config.recover
+                        && matches!(stop_reason, Some(Stop::Fail(_)) | Some(Stop::Mismatch(_)))
+                    {
+                        log::info!("Dropping the packages of the failed run");
+                        assign_queue.clear();
+                        timer_vec.clear();
+                        discarded.extend(assigned_job_identifier_hashmap.keys().cloned());
+                        tx.send(Message::completed(Err(stop_reason.take().unwrap())))
+                            .unwrap();
+                    }
+                    if stop_reason.is_some() {
+                        log::trace!("Kernel stopping on exit request...");
+                        for thread in thread_vec {
+                            thread.sender.send(Message::Exit).unwrap();
+                            thread.handle.join().unwrap();
+                        }
+                        log::info!("Kernel stopped.");
+                        return stop_reason;
+                    }
+                    //Fire timers
+                    let now = config.clock.now();
+                    while !timer_vec.is_empty() && timer_vec[0].0 <= now {
+                        let (_, package_vector) = timer_vec.remove(0);
+                        assign_queue.extend(package_vector);
+                    }
                     //Assign packages
                     let mut idle_thread_vec: Vec<&Thread> = Vec::new();
                     for thread in &thread_vec {
@@ -262,10 +1066,10 @@ impl Kernel {
                             let (sender, rx) = mpsc::channel();
                             let (tx, receiver) = mpsc::channel();
                             let script_map = Arc::clone(&script_hashmap);
+                            let config = Arc::clone(&config);
                             let thread_handle = thread::Builder::new()
                                 .name(thread_name)
                                 .spawn(move || {
-                                    //Some
                                     let builtin_hashmap = exec::builtin_hashmap();
                                     loop {
                                         let message_wrapped = rx.try_recv();
@@ -273,165 +1077,19 @@ impl Kernel {
                                             match message {
                                                 Message::Package(package) => {
                                                     log::trace!("Thread is executing package");
-                                                    //Variable collection
-                                                    let mut variable_vector = Vec::new();
-                                                    let variable_hashmap =
-                                                        package.variable.read().unwrap();
-                                                    for variable_name in &package
-                                                        .expression
-                                                        .read()
-                                                        .unwrap()
-                                                        .variables
-                                                    {
-                                                        if variable_name.starts_with('"')
-                                                            && variable_name.ends_with('"')
-                                                        {
-                                                            log::info!(
-                                                                "Variable {} seems to be a string.",
-                                                                variable_name
-                                                            );
-                                                            variable_vector.push(Arc::new(
-                                                                RwLock::new(
-                                                                    variables::Primitive::Literal(
-                                                                        variable_name[1
-                                                                            ..variable_name.len()
-                                                                                - 1]
-                                                                            .to_string(),
-                                                                    ),
-                                                                ),
-                                                            ));
-                                                        } else {
-                                                            variable_vector.push(Arc::clone(
-                                                                variable_hashmap
-                                                                    .get(variable_name)
-                                                                    .unwrap(),
-                                                            ));
-                                                        }
-                                                    }
-                                                    let mut result = variables::Complex::Primitive(
-                                                        variables::Primitive::Nil,
+                                                    let result = std::panic::catch_unwind(
+                                                        std::panic::AssertUnwindSafe(|| {
+                                                            package.execute(
+                                                                &builtin_hashmap,
+                                                                &script_map,
+                                                                &config,
+                                                            )
+                                                        }),
                                                     );
-                                                    //Operation
-                                                    match &package
-                                                        .expression
-                                                        .read()
-                                                        .unwrap()
-                                                        .operation
-                                                    {
-                                                        structures::Operation::Builtin(
-                                                            builtin_command,
-                                                        ) => {
-                                                            let op_function = builtin_hashmap
-                                                                .get(builtin_command)
-                                                                .unwrap();
-                                                            result = op_function(&variable_vector);
-                                                        }
-                                                        structures::Operation::External(
-                                                            script_name,
-                                                            function_name,
-                                                        ) => {
-                                                            //Transfowm variable vector
-                                                            let mut new_variable_vec = Vec::new();
-                                                            for variable in variable_vector {
-                                                                new_variable_vec.push(
-                                                                    variable
-                                                                        .read()
-                                                                        .unwrap()
-                                                                        .clone(),
-                                                                );
-                                                            }
-                                                            let script = script_map.read().unwrap();
-                                                            let function = script
-                                                                .get(script_name)
-                                                                .unwrap()
-                                                                .get(function_name)
-                                                                .unwrap();
-                                                            let expression_pack =
-                                                                ExpressionPackage::from_function(
-                                                                    function,
-                                                                    new_variable_vec,
-                                                                );
-                                                            let result_variable = expression_pack[expression_pack.len()].variable.clone();
-                                                            for single_expression in expression_pack
-                                                            {
-                                                                let identifier =
-                                                                    single_expression.identifier;
-                                                                tx.send(Message::Package(
-                                                                    single_expression,
-                                                                ))
-                                                                .unwrap();
-                                                                let mut sented = false;
-                                                                while !sented {
-                                                                    if let Ok(msg) = rx.try_recv() {
-                                                                        if let Message::PackageReceived = msg {
-                                                                            sented = true;
-                                                                        }
-                                                                    }
-                                                                }
-                                                                let mut completed = false;
-                                                                while !completed {
-                                                                    tx.send(Message::Query(identifier)).unwrap();
-                                                                    let mut sented = false;
-                                                                    while !sented {
-                                                                        if let Ok(msg) = rx.try_recv() {
-                                                                            if let Message::QueryResult(result) = msg {
-                                                                                sented = true;
-                                                                                if let QueryResult::Completed = result {
-                                                                                    completed = true;
-                                                                                }
-                                                                            }
-                                                                        }
-                                                                    }
-                                                                }
-                                                            }
-                                                            //Get result variable
-                                                            if let Some(variable_name) = &function.output {
-                                                                result = variables::Complex::Primitive(result_variable.read().unwrap().get(variable_name).unwrap().read().unwrap().clone());
-                                                            };
-                                                        }
-                                                    };
-                                                    //To
-                                                    let mut thread_result = Message::Complete;
-                                                    match &package.expression.read().unwrap().to {
-                                                        structures::ExpressionTo::ToVar(variable_name) => {
-                                                            package.variable.write().unwrap().insert(variable_name.to_string(), if let variables::Complex::Primitive(variable_content) = result {
-                                                                Arc::new(RwLock::new(variable_content))
-                                                            } else {
-                                                                log::error!("Unexpected variable type");
-                                                                return;
-                                                            });
-                                                        }
-                                                        structures::ExpressionTo::ToBlock(block) => {
-                                                            let mut blocks: Vec<ExpressionPackage> = Vec::new();
-                                                            match result {
-                                                                variables::Complex::Primitive(single_result) => {
-                                                                    let mut variable_hmap = HashMap::new();
-                                                                    for (variable_name, variable_content) in &*package.variable.read().unwrap() {
-                                                                        variable_hmap.insert(variable_name.to_string(), variable_content.read().unwrap().clone());
-                                                                    }
-                                                                    variable_hmap.insert(String::from("this"), single_result);
-                                                                    for pack in ExpressionPackage::from_vec_expression(block.to_vec(), variable_hmap) {
-                                                                        blocks.push(pack);
-                                                                    }
-                                                                },
-                                                                variables::Complex::Stack(stack_result) => {
-                                                                    for single_result in stack_result {
-                                                                        let mut variable_hmap = HashMap::new();
-                                                                        for (variable_name, variable_content) in &*package.variable.read().unwrap() {
-                                                                            variable_hmap.insert(variable_name.to_string(), variable_content.read().unwrap().clone());
-                                                                        }
-                                                                        variable_hmap.insert(String::from("this"), single_result);
-                                                                        for pack in ExpressionPackage::from_vec_expression(block.to_vec(), variable_hmap) {
-                                                                            blocks.push(pack);
-                                                                        }
-                                                                    }
-                                                                },
-                                                            }
-                                                            thread_result = Message::CompleteWithPackage(blocks);
-                                                        },
-                                                        structures::ExpressionTo::Nil => {},
-                                                    }
-                                                    tx.send(thread_result).unwrap();
+                                                    tx.send(result.unwrap_or_else(|panic| {
+                                                        Message::Fail(panic_message(panic))
+                                                    }))
+                                                    .unwrap();
                                                 }
                                                 Message::Exit => {
                                                     log::info!("Thread stopping");
@@ -450,11 +1108,13 @@ impl Kernel {
                                     }
                                 })
                                 .unwrap();
+                            // How many threads are spawned depends on timing, so their identifiers
+                            // don't draw from the seeded generator of package identifiers
                             let thread_identifier = {
-                                let mut rng = rand::thread_rng();
+                                let mut thread_rng = rand::thread_rng();
                                 let mut identifier = [0; THREAD_IDENTIFIER_LENGTH];
                                 for identifier_byte in identifier.iter_mut() {
-                                    *identifier_byte = rng.gen();
+                                    *identifier_byte = thread_rng.gen();
                                 }
                                 identifier
                             };
@@ -474,17 +1134,65 @@ impl Kernel {
                         }
                     }
                     for thread in idle_thread_vec {
-                        if !assign_queue.is_empty() {
+                        if paused && !step {
+                            break;
+                        }
+                        if let Some(package) = assign_queue.first() {
+                            if paused {
+                                step = false;
+                                report = true;
+                            } else if released != Some(package.identifier)
+                                && locations
+                                    .get(&(Arc::as_ptr(&package.expression) as usize))
+                                    .is_some_and(|location| {
+                                        breakpoints
+                                            .iter()
+                                            .any(|breakpoint| breakpoint.matches(location))
+                                    })
+                            {
+                                log::trace!("Package {} reached a breakpoint", package);
+                                paused = true;
+                                report = true;
+                                break;
+                            }
+                            released = None;
                             let package = assign_queue.remove(0);
-                            log::trace!("Assigned thread identifier: {}, job identifier: {}", thread, package);
-                            assigned_job_identifier_hashmap.insert(thread.identifier, package.identifier);
-                            thread
-                                .sender
-                                .send(Message::Package(package))
-                                .unwrap();
+                            log::trace!(
+                                "Assigned thread identifier: {}, job identifier: {}",
+                                thread,
+                                package
+                            );
+                            assigned_job_identifier_hashmap
+                                .insert(thread.identifier, package.identifier);
+                            thread.sender.send(Message::Package(package)).unwrap();
                             thread.state = ThreadState::Busy;
                         }
                     }
+                    if assign_queue.is_empty()
+                        && assigned_job_identifier_hashmap.is_empty()
+                        && !timer_vec.is_empty()
+                        && (!paused || step)
+                    {
+                        config.clock.wait_until(timer_vec[0].0);
+                    }
+                    // Nothing is left to report once every package has been executed
+                    if paused
+                        && report
+                        && assigned_job_identifier_hashmap.is_empty()
+                        && !(assign_queue.is_empty() && timer_vec.is_empty())
+                    {
+                        let packages = assign_queue
+                            .iter()
+                            .map(|package| package.state(&locations, false))
+                            .chain(timer_vec.iter().flat_map(|(_, package_vector)| {
+                                package_vector
+                                    .iter()
+                                    .map(|package| package.state(&locations, true))
+                            }))
+                            .collect();
+                        tx.send(Message::Paused(packages)).unwrap();
+                        report = false;
+                    }
                     if now_stop {
                         let mut can_stop = true;
                         for thread in &thread_vec {
@@ -492,7 +1200,10 @@ impl Kernel {
                                 can_stop = false;
                             }
                         }
-                        if (!assigned_job_identifier_hashmap.is_empty()) || (!assign_queue.is_empty()) {
+                        if (!assigned_job_identifier_hashmap.is_empty())
+                            || (!assign_queue.is_empty())
+                            || (!timer_vec.is_empty())
+                        {
                             can_stop = false;
                         }
                         if can_stop {
@@ -502,7 +1213,7 @@ impl Kernel {
                                 thread.handle.join().unwrap();
                             }
                             log::info!("Kernel stopped.");
-                            return;
+                            return None;
                         }
                     }
                 }
@@ -526,12 +1237,581 @@ impl Kernel {
             }
         }
     }
+    /// Send a command to a kernel created with `Config::debug`, and wait until it pauses again
+    ///
+    /// Returns the packages waiting to be executed, or `None` if the kernel stopped instead.
+    pub fn debug(&self, command: Message) -> Option<Vec<debug::PackageState>> {
+        self.sender.send(command).ok()?;
+        loop {
+            match self.receiver.recv() {
+                Ok(Message::Paused(packages)) => return Some(packages),
+                Ok(_) => (),
+                Err(_) => return None,
+            }
+        }
+    }
+    /// Wait until a package fails on a kernel created with `Config::recover`, and return why
+    ///
+    /// Returns `None` if the kernel stopped instead, such as after a script called `exit`.
+    pub fn failure(&self) -> Option<Stop> {
+        loop {
+            match self.receiver.recv() {
+                Ok(Message::Fail(reason)) => return Some(Stop::Fail(reason)),
+                Ok(Message::Mismatch(reason)) => return Some(Stop::Mismatch(reason)),
+                Ok(_) => (),
+                Err(_) => return None,
+            }
+        }
+    }
+    /// Whether the kernel has stopped, such as after a script called `exit`
+    pub fn stopped(&self) -> bool {
+        self.handle.is_finished()
+    }
+    /// Stop without executing the packages that are left
     pub fn stop(self) {
-        self.sender.send(Message::Exit).unwrap();
+        // The kernel has already stopped if a script called `exit`
+        let _ = self.sender.send(Message::Exit);
         self.handle.join().unwrap();
     }
-    pub fn grace_stop(self) {
-        self.sender.send(Message::ExitGracefully).unwrap();
-        self.handle.join().unwrap();
+    /// Stop without executing the packages that are left, and without waiting for the kernel
+    ///
+    /// A package that is blocked, such as on `read_line`, keeps its thread until it returns.
+    pub fn detach(self) {
+        // The kernel has already stopped if a script called `exit`
+        let _ = self.sender.send(Message::Exit);
+    }
+    /// Wait until every package has been executed, and return why the kernel stopped early (if it did)
+    pub fn grace_stop(self) -> Option<Stop> {
+        // The kernel has already stopped if a script called `exit`
+        let _ = self.sender.send(Message::ExitGracefully);
+        self.handle.join().unwrap()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// Line that `stack123` writes before returning its stack
+    const STACK123: &str = "you have called stack123.";
+    fn launch(
+        config: Config,
+        scripts: &[(&str, &str)],
+        main_script: &str,
+        memory: &Arc<Mutex<String>>,
+    ) -> Kernel {
+        let _ = pretty_env_logger::try_init();
+        let threads = Kernel::with_config(config);
+        for (script_name, script) in scripts {
+            threads.send_message(Message::NewScript(
+                script_name.to_string(),
+                structures::new_script(script).unwrap(),
+            ));
+        }
+        let main_script = structures::new_script(main_script).unwrap();
+        let (packages, _) = ExpressionPackage::from_function_with(
+            main_script.get("main").unwrap(),
+            Vec::new(),
+            exec::Output::Memory(Arc::clone(memory)),
+        )
+        .unwrap();
+        for package in packages {
+            threads.send_package(package);
+        }
+        threads
+    }
+    /// Output of running `main_script`, and why the kernel stopped early (if it did)
+    fn run(config: Config, scripts: &[(&str, &str)], main_script: &str) -> (String, Option<Stop>) {
+        let memory = Arc::new(Mutex::new(String::new()));
+        let stop = launch(config, scripts, main_script, &memory).grace_stop();
+        let result = memory.lock().unwrap().clone();
+        (result, stop)
+    }
+    /// Why the kernel stopped after running `main_script`
+    fn stop(scripts: &[(&str, &str)], main_script: &str) -> Option<Stop> {
+        run(Config::default(), scripts, main_script).1
+    }
+    #[test]
+    fn sequential_call() {
+        let (output, _) = run(
+            Config::default(),
+            &[("lib", "twice<r>(x){r=id@lib(x);print(r);}id<x>(x){}")],
+            "main<>(){a=twice@lib(\"one\");print(a);print(\"two\");}",
+        );
+        assert_eq!(output, "one\none\ntwo\n");
+    }
+    #[test]
+    fn unknown_builtin() {
+        assert_eq!(
+            stop(&[], "main<>(){foo(1);print(\"after\");}"),
+            Some(Stop::Fail(String::from("Unknown function `foo`")))
+        );
+    }
+    #[test]
+    fn undefined_variable() {
+        assert_eq!(
+            stop(&[], "main<>(){print(missing);}"),
+            Some(Stop::Fail(String::from(
+                "Variable `missing` is not defined"
+            )))
+        );
+    }
+    #[test]
+    fn block_join() {
+        let (output, _) = run(
+            Config::default(),
+            &[],
+            "main<>(){stack123()=>{print(this);};print(\"end\");}",
+        );
+        let mut lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.remove(0), STACK123);
+        assert_eq!(lines.pop(), Some("end"));
+        lines.sort();
+        assert_eq!(lines, vec!["1", "2", "3"]);
+    }
+    #[test]
+    fn body_order() {
+        // Every expression sees the assignments of the expressions before it
+        for _ in 0..20 {
+            let (output, _) = run(
+                Config::default(),
+                &[("lib", "id<x>(x){}")],
+                "main<>(){a=id@lib(\"1\");print(a);b=id@lib(a);print(\"2\");print(b);print(\"3\");}",
+            );
+            assert_eq!(output, "1\n2\n1\n3\n");
+        }
+    }
+    #[test]
+    fn block_body_order() {
+        let (output, _) = run(
+            Config::default(),
+            &[("lib", "id<x>(x){}")],
+            "main<>(){stack123()=>{x=id@lib(this);print(x);};print(\"end\");}",
+        );
+        let mut lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.remove(0), STACK123);
+        assert_eq!(lines.pop(), Some("end"));
+        lines.sort();
+        assert_eq!(lines, vec!["1", "2", "3"]);
+    }
+    #[test]
+    fn block_ordered_output() {
+        let (output, _) = run(
+            Config {
+                ordered_output: true,
+                ..Config::default()
+            },
+            &[],
+            "main<>(){stack123()=>{print(this);print(this);};print(\"end\");}",
+        );
+        assert_eq!(output, format!("{}\n1\n1\n2\n2\n3\n3\nend\n", STACK123));
+    }
+    #[test]
+    fn exit_code() {
+        let (output, stopped) = run(
+            Config {
+                arguments: vec![String::from("a"), String::from("b")],
+                ..Config::default()
+            },
+            &[],
+            "main<>(){args()=>{print(this);};exit(3);print(\"unreachable\");}",
+        );
+        let mut lines = output.lines().collect::<Vec<&str>>();
+        lines.sort();
+        assert_eq!(lines, vec!["a", "b"]);
+        assert_eq!(stopped, Some(Stop::Exit(3)));
+    }
+    #[test]
+    fn exit_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){exit(\"x\");}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `exit` expects an integer, found String"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){exit(4294967296);}"),
+            Some(Stop::Fail(String::from(
+                "Exit code 4294967296 is out of range"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){env();}"),
+            Some(Stop::Fail(String::from(
+                "Function `env` expects 1 arguments, found 0"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){print();}"),
+            Some(Stop::Fail(String::from(
+                "Function `print` expects 1 arguments, found 0"
+            )))
+        );
+    }
+    #[test]
+    fn virtual_clock_sleep() {
+        let (output, _) = run(
+            Config {
+                clock: Arc::new(clock::VirtualClock::default()),
+                ..Config::default()
+            },
+            &[],
+            "main<>(){s=now();sleep(60000);e=elapsed(s);print(e);stack123()=>{sleep(this);print(this);};}",
+        );
+        assert_eq!(output, format!("60000\n{}\n1\n2\n3\n", STACK123));
+    }
+    #[test]
+    fn sleep_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){sleep(\"x\");}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `sleep` expects an integer, found String"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){sleep(-1);}"),
+            Some(Stop::Fail(String::from(
+                "Function `sleep` expects a duration of at least 0"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){elapsed();}"),
+            Some(Stop::Fail(String::from(
+                "Function `elapsed` expects 1 arguments, found 0"
+            )))
+        );
+    }
+    #[test]
+    fn seeded_random() {
+        let seeded_run = |seed| {
+            run(
+                Config {
+                    ordered_output: true,
+                    seed: Some(seed),
+                    ..Config::default()
+                },
+                &[],
+                "main<>(){s=stack123();shuffle(s)=>{print(this);r=random_int(1,1000000);print(r);};c=choice(s);print(c);}",
+            )
+            .0
+        };
+        assert_eq!(seeded_run(42), seeded_run(42));
+        assert_ne!(seeded_run(42), seeded_run(43));
+    }
+    #[test]
+    fn random_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){random_int(2,1);}"),
+            Some(Stop::Fail(String::from(
+                "Function `random_int` expects `lo` to be at most `hi`"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){random_int(1);}"),
+            Some(Stop::Fail(String::from(
+                "Function `random_int` expects 2 arguments, found 1"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){shuffle(\"abc\");}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `shuffle` expects Stack, found String"
+            )))
+        );
+    }
+    #[test]
+    fn function_result() {
+        let threads = Kernel::default();
+        let script = structures::new_script("main<r>(m){r=json_stringify(m);}").unwrap();
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(
+            String::from("b"),
+            variables::Complex::Primitive(variables::Primitive::Int(2)),
+        );
+        map.insert(
+            String::from("a"),
+            variables::Complex::Primitive(variables::Primitive::Literal(String::from("1"))),
+        );
+        let (packages, result) = ExpressionPackage::from_function(
+            script.get("main").unwrap(),
+            vec![variables::Complex::Map(map)],
+        )
+        .unwrap();
+        for package in packages {
+            threads.send_package(package);
+        }
+        threads.grace_stop();
+        assert_eq!(result.recv().unwrap().to_string(), r#"{"a":"1","b":2}"#);
+    }
+    #[test]
+    fn json_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){json_parse(1);}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `json_parse` expects String, found Int"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){json_stringify();}"),
+            Some(Stop::Fail(String::from(
+                "Function `json_stringify` expects 1 arguments, found 0"
+            )))
+        );
+    }
+    #[test]
+    fn map_block() {
+        let (output, _) = run(
+            Config {
+                ordered_output: true,
+                ..Config::default()
+            },
+            &[],
+            "main<>(){m=map_new();m=set(m,\"b\",2);m=set(m,\"a\",1);h=has(m,\"a\");print(h);v=get(m,\"b\");print(v);keys(m)=>{print(this);};set(m,\"c\",3)=>{print(key);print(value);};}",
+        );
+        assert_eq!(output, "true\n2\na\nb\na\n1\nb\n2\nc\n3\n");
+    }
+    #[test]
+    fn map_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();get(1,s);}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `get` expects Map or Stack, found Int"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();set(s,\"a\",1);}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `set` expects Map, found Stack"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){m=map_new();has(m);}"),
+            Some(Stop::Fail(String::from(
+                "Function `has` expects 2 arguments, found 1"
+            )))
+        );
+    }
+    #[test]
+    fn function_reference() {
+        let (output, _) = run(
+            Config::default(),
+            &[(
+                "lib",
+                "apply<r>(f,x){r=call(f,x);}shout<x>(x){print(x);}",
+            )],
+            "main<>(){f=ref(shout@lib);r=apply@lib(f,\"hi\");print(r);print(f);g=ref(\"apply@lib\");r=call(g,f,\"again\");print(r);}",
+        );
+        assert_eq!(output, "hi\nhi\nshout@lib\nagain\nagain\n");
+    }
+    #[test]
+    fn unknown_external_function() {
+        let expected = Some(Stop::Fail(String::from("Function `f@lib` not found")));
+        assert_eq!(stop(&[("lib", "g<>(){}")], "main<>(){f@lib();}"), expected);
+        assert_eq!(stop(&[], "main<>(){f@lib();}"), expected);
+        assert_eq!(
+            stop(&[("lib", "g<>(){}")], "main<>(){call(f@lib);}"),
+            expected
+        );
+    }
+    #[test]
+    fn function_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){ref();}"),
+            Some(Stop::Fail(String::from(
+                "Function `ref` expects 1 arguments, found 0"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){ref(\"nope\");}"),
+            Some(Stop::Fail(String::from(
+                "Function `ref` expects a function, such as function@script or script::function"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){call(\"f@lib\");}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `call` expects Function, found String"
+            )))
+        );
+    }
+    #[test]
+    fn type_annotation() {
+        let (output, stopped) = run(
+            Config::default(),
+            &[("lib", "echo<x:Int>(x:Int){}")],
+            "main<>(){a=echo@lib(3);print(a);b=echo@lib(\"3\");print(b);}",
+        );
+        assert_eq!(output, "3\n");
+        assert!(matches!(stopped, Some(Stop::Mismatch(_))));
+        assert_eq!(
+            stop(
+                &[("lib", "echo<x:Int>(x:Int){}")],
+                "main<>(){b=echo@lib(\"3\");}"
+            ),
+            Some(Stop::Mismatch(String::from(
+                "Parameter `x` of function `echo` expects Int, found String"
+            )))
+        );
+        assert_eq!(
+            stop(
+                &[("lib", "text<x:Int>(){x=json_stringify(1);}")],
+                "main<>(){a=text@lib();print(a);}"
+            ),
+            Some(Stop::Mismatch(String::from(
+                "Result `x` of function `text` expects Int, found String"
+            )))
+        );
+        let script = structures::new_script("main<>(a:Stack,b){}").unwrap();
+        let function = script.get("main").unwrap();
+        let input = vec![
+            variables::Complex::Stack(Vec::new()),
+            variables::Complex::Primitive(variables::Primitive::Nil),
+        ];
+        assert!(ExpressionPackage::from_function(function, input).is_ok());
+        let input = vec![
+            variables::Complex::Primitive(variables::Primitive::Nil),
+            variables::Complex::Primitive(variables::Primitive::Nil),
+        ];
+        assert_eq!(
+            ExpressionPackage::from_function(function, input).unwrap_err(),
+            "Parameter `a` of function `main` expects Stack, found Nil"
+        );
+    }
+    #[test]
+    fn block_scope() {
+        let (output, _) = run(
+            Config {
+                ordered_output: true,
+                ..Config::default()
+            },
+            &[],
+            "main<>(){a=json_parse(\"1\");stack123()=>{print(a);a=json_parse(this);print(a);};print(a);}",
+        );
+        assert_eq!(output, format!("{}\n1\n1\n1\n2\n1\n3\n1\n", STACK123));
+    }
+    #[test]
+    fn collect_block() {
+        let (output, _) = run(
+            Config::default(),
+            &[("lib", "wrap<m>(x){m=map_new();m=set(m,\"x\",x);}")],
+            "main<>(){w=stack123()=><y>{y=wrap@lib(this);};print(w);e=map_new()=><y>{};print(e);w=stack123()=><y>{print(this);};print(w);}",
+        );
+        let mut lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.remove(0), STACK123);
+        assert_eq!(lines.remove(0), r#"[{"x":"1"},{"x":"2"},{"x":"3"}]"#);
+        assert_eq!(lines.remove(0), "[]");
+        assert_eq!(lines.remove(0), STACK123);
+        assert_eq!(lines.pop(), Some("[null,null,null]"));
+        lines.sort();
+        assert_eq!(lines, vec!["1", "2", "3"]);
+    }
+    #[test]
+    fn reduce() {
+        let (output, _) = run(
+            Config::default(),
+            &[("lib", "join<r>(a,b){r=concat(a,b);}")],
+            "main<>(){w=stack123()=><y>{y=concat(this,\"!\");};s=reduce(w,join@lib,\">\");print(s);c=stack123()=><y>{y=len(this);};n=reduce(c,\"add\");print(n);f=reduce(c,\"add\",0.5);print(f);e=map_new();e=values(e);e=reduce(e,\"add\");print(e);}",
+        );
+        assert_eq!(output, format!("{0}\n>1!2!3!\n{0}\n3\n3.5\n\n", STACK123));
+    }
+    #[test]
+    fn reduce_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();reduce(s);}"),
+            Some(Stop::Fail(String::from(
+                "Function `reduce` expects 2 arguments, found 1"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();reduce(s,\"fail\");}"),
+            Some(Stop::Fail(String::from("1")))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();reduce(s,\"sleep\");}"),
+            Some(Stop::Fail(String::from(
+                "Function `sleep` can't be used with `reduce`"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();reduce(s,\"nope\");}"),
+            Some(Stop::Fail(String::from("Unknown function `nope`")))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();reduce(s,f@lib);}"),
+            Some(Stop::Fail(String::from("Function `f@lib` not found")))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){add(\"a\",1);}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `add` expects Int or Float, found String"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){len(1);}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `len` expects Stack or Map or String, found Int"
+            )))
+        );
+    }
+    #[test]
+    fn bytes() {
+        let path = std::env::temp_dir().join(format!("awsl-bytes-{}", std::process::id()));
+        let (output, _) = run(
+            Config::default(),
+            &[],
+            &format!(
+                "main<>(){{b=hex_decode(\"00ff10\");print(b);l=bytes_len(b);print(l);s=slice(b,1);s=hex_encode(s);print(s);d=base64_decode(\"AP8Q\");d=hex_encode(d);print(d);w=write_file(\"{0}\",b);print(w);r=read_bytes(\"{0}\");r=hex_encode(r);print(r);}}",
+                path.display()
+            ),
+        );
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(output, "AP8Q\n3\nff10\n00ff10\ntrue\n00ff10\n");
+    }
+    #[test]
+    fn assert_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){assert(1);}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `assert` expects Bool, found Int"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){assert_eq(1);}"),
+            Some(Stop::Fail(String::from(
+                "Function `assert_eq` expects 2 arguments, found 1"
+            )))
+        );
+    }
+    #[test]
+    fn bytes_arguments() {
+        assert_eq!(
+            stop(&[], "main<>(){s=stack123();hex_encode(s);}"),
+            Some(Stop::Fail(String::from(
+                "Argument 1 of function `hex_encode` expects Bytes or String, found Stack"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){slice(\"abc\",\"x\");}"),
+            Some(Stop::Fail(String::from(
+                "Argument 2 of function `slice` expects an integer, found String"
+            )))
+        );
+        assert_eq!(
+            stop(&[], "main<>(){write_file(\"x\");}"),
+            Some(Stop::Fail(String::from(
+                "Function `write_file` expects 2 arguments, found 1"
+            )))
+        );
+    }
+    #[test]
+    fn nested_block() {
+        let (output, _) = run(
+            Config::default(),
+            &[],
+            "main<>(){s=stack123();m=map_new();m=set(m,\"x\",s);m=set(m,\"y\",s);print(m);values(m)=>{shuffle(this)=>{print(this);};};}",
+        );
+        let mut lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.remove(0), STACK123);
+        assert_eq!(lines.remove(0), r#"{"x":["1","2","3"],"y":["1","2","3"]}"#);
+        lines.sort();
+        assert_eq!(lines, vec!["1", "1", "2", "2", "3", "3"]);
     }
 }
@@ -0,0 +1,451 @@
+use super::error::{Error, Kind};
+use super::{manifest, structures, variables};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+/// Path that reads a script from the standard input
+pub const STDIN: &str = "-";
+/// Module name of a script read from the standard input
+const STDIN_NAME: &str = "<stdin>";
+/// Scripts loaded from files, by module name
+///
+/// A script may declare its module name with `module "name";` before anything else.
+/// Otherwise it's named after its canonical path, so every path to the same file
+/// (such as `./lib/a.awsl` and `lib/a.awsl`) is the same module.
+///
+/// Imports are declared next, as `import "path.awsl" as lib;`, where the path is relative to
+/// the importing file (see `import_path`). Calls such as `function@lib` or `lib::function` are
+/// resolved to the imported module.
+///
+/// The path `-` reads a script from the standard input, named `<stdin>` unless it declares a
+/// module, which imports relative to the current directory.
+#[derive(Default)]
+pub struct Modules {
+    scripts: HashMap<String, structures::Script>,
+    /// Module name of every loaded file
+    names: HashMap<PathBuf, String>,
+    /// Every file that was read, including those that failed to load
+    files: BTreeSet<PathBuf>,
+    /// Module name of every import alias, by importing file
+    aliases: HashMap<PathBuf, HashMap<String, String>>,
+    /// Text used instead of the content of a file, such as an unsaved buffer of an editor
+    sources: HashMap<PathBuf, String>,
+    manifest: manifest::Manifest,
+}
+impl Modules {
+    /// Resolve imports through the search paths and dependencies of a project
+    pub fn with_manifest(manifest: manifest::Manifest) -> Self {
+        Self {
+            manifest,
+            ..Self::default()
+        }
+    }
+    /// Use `text` instead of the content of the file at `path`, which should be canonical
+    pub fn set_source(&mut self, path: PathBuf, text: String) {
+        self.sources.insert(path, text);
+    }
+    /// Load a script file and every script it imports, and return its module name
+    pub fn load(&mut self, path: &Path) -> Result<String, Error> {
+        self.load_file(path, &mut Vec::new())
+    }
+    /// Canonical path of every file that was read, including those that failed to load
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.files.iter()
+    }
+    /// Canonical path and module name of every loaded file
+    pub fn modules(&self) -> impl Iterator<Item = (&PathBuf, &String)> {
+        self.names.iter()
+    }
+    /// Module name of every alias imported by a file, even if the file failed to load later on
+    pub fn aliases(&self, path: &Path) -> Option<&HashMap<String, String>> {
+        self.aliases.get(path)
+    }
+    /// Every loaded script, by module name
+    ///
+    /// Calls to a path of a loaded file (such as `function@lib/a.awsl`), rather than to its module
+    /// name, are resolved as well.
+    pub fn finish(self) -> HashMap<String, structures::Script> {
+        let mut aliases = HashMap::new();
+        for script in self.scripts.values() {
+            for function in script.values() {
+                resolve_paths(&function.process, &self.names, &mut aliases);
+            }
+        }
+        for script in self.scripts.values() {
+            for function in script.values() {
+                resolve(&function.process, &aliases);
+            }
+        }
+        self.scripts
+    }
+    fn load_file(&mut self, path: &Path, importing: &mut Vec<PathBuf>) -> Result<String, Error> {
+        let stdin = path == Path::new(STDIN);
+        let canonical = if stdin {
+            std::env::current_dir().map(|directory| directory.join(STDIN_NAME))
+        } else {
+            path.canonicalize()
+        }
+        .map_err(|e| {
+            Error::new(
+                Kind::NotFound,
+                format!("Unable to read {}: {}", path.display(), e),
+            )
+        })?;
+        if let Some(position) = importing.iter().position(|i| *i == canonical) {
+            let mut cycle = importing[position..]
+                .iter()
+                .map(|i| i.display().to_string())
+                .collect::<Vec<String>>();
+            cycle.push(canonical.display().to_string());
+            return Err(Error::new(
+                Kind::Invalid,
+                format!("Import cycle: {}", cycle.join(" -> ")),
+            ));
+        }
+        if let Some(name) = self.names.get(&canonical) {
+            return Ok(name.to_string());
+        }
+        let script = if stdin {
+            let mut script = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut script).map(|_| script)
+        } else if let Some(source) = self.sources.get(&canonical) {
+            Ok(source.to_string())
+        } else {
+            self.files.insert(canonical.clone());
+            std::fs::read_to_string(&canonical)
+        }
+        .map_err(|e| {
+            Error::new(
+                Kind::NotFound,
+                format!("Unable to read {}: {}", canonical.display(), e),
+            )
+        })?;
+        let mut script = strip_whitespace(split_shebang(&script).1);
+        let name = match parse_declaration(&script, "module\"") {
+            Some((name, rest)) => {
+                script = rest;
+                name
+            }
+            None if stdin => STDIN_NAME.to_string(),
+            None => canonical.display().to_string(),
+        };
+        if let Some((other, _)) = self.names.iter().find(|(_, other)| **other == name) {
+            return Err(Error::new(
+                Kind::Invalid,
+                format!(
+                    "Module {} is declared by both {} and {}",
+                    name,
+                    other.display(),
+                    canonical.display()
+                ),
+            ));
+        }
+        let mut aliases = HashMap::new();
+        importing.push(canonical.clone());
+        let invalid_import = || {
+            Error::new(
+                Kind::Invalid,
+                format!("Invalid import in {}", canonical.display()),
+            )
+        };
+        while script.starts_with("import\"") {
+            let (import_path, rest) =
+                parse_declaration(&script, "import\"").ok_or_else(invalid_import)?;
+            let (import_path, alias) = match import_path.find("\"as") {
+                Some(offset) if offset + 3 < import_path.len() => (
+                    import_path[..offset].to_string(),
+                    import_path[offset + 3..].to_string(),
+                ),
+                _ => return Err(invalid_import()),
+            };
+            let import_path = self
+                .import_path(canonical.parent().unwrap(), &import_path)
+                .map_err(|e| Error::new(Kind::Invalid, e))?;
+            let import_name = self.load_file(&import_path, importing)?;
+            aliases.insert(alias, import_name);
+            script = rest;
+        }
+        importing.pop();
+        self.aliases.insert(canonical.clone(), aliases.clone());
+        let script = structures::new_script(&script)
+            .map_err(|e| Error::new(Kind::Invalid, format!("{}: {}", canonical.display(), e)))?;
+        for function in script.values() {
+            resolve(&function.process, &aliases);
+        }
+        self.names.insert(canonical, name.to_string());
+        self.scripts.insert(name.to_string(), script);
+        Ok(name)
+    }
+    /// Path of a file imported from a script in `directory`
+    ///
+    /// An import starting with the name of a dependency is looked up in the directory of the
+    /// dependency (and the name alone is its entry script). Anything else is looked up next to
+    /// the importing script, and then in the search paths of the manifest.
+    fn import_path(&self, directory: &Path, import: &str) -> Result<PathBuf, String> {
+        let mut components = import.splitn(2, '/');
+        if let Some(dependency) = self.manifest.dependencies.get(components.next().unwrap()) {
+            return match components.next() {
+                Some(rest) => Ok(dependency.join(rest)),
+                None => manifest::Manifest::dependency_entry(dependency),
+            };
+        }
+        let path = directory.join(import);
+        if path.exists() {
+            return Ok(path);
+        }
+        for search_path in &self.manifest.paths {
+            let path = search_path.join(import);
+            if path.exists() {
+                return Ok(path);
+            }
+        }
+        Ok(directory.join(import))
+    }
+}
+/// Split a script into its `#!` line (with the line break, if there is one) and the rest
+pub fn split_shebang(script: &str) -> (&str, &str) {
+    if !script.starts_with("#!") {
+        return ("", script);
+    }
+    match script.find('\n') {
+        Some(end) => script.split_at(end + 1),
+        None => (script, ""),
+    }
+}
+/// Delete all whitespace outside of strings
+pub fn strip_whitespace(script: &str) -> String {
+    let mut script = script.to_string();
+    let mut branches_count = 0;
+    script.retain(|c| {
+        (
+            (!c.is_whitespace()) || (branches_count % 2 == 1),
+            if c == '"' {
+                branches_count += 1
+            },
+        )
+            .0
+    });
+    script
+}
+/// Split `KEYWORD"CONTENT";REST` (without whitespace) into the content and the rest
+///
+/// The content ends at the first `;`, without the closing quote if it's the last character
+fn parse_declaration(script: &str, keyword: &str) -> Option<(String, String)> {
+    let rest = script.strip_prefix(keyword)?;
+    let end = rest.find(';')?;
+    let content = &rest[..end];
+    let content = content.strip_suffix('"').unwrap_or(content);
+    if content.is_empty() {
+        return None;
+    }
+    Some((content.to_string(), rest[end + 1..].to_string()))
+}
+/// Replace aliases with module names, in calls and function references
+fn resolve(body: &[Arc<RwLock<structures::Expression>>], aliases: &HashMap<String, String>) {
+    for expression in body {
+        let mut expression = expression.write().unwrap();
+        if let structures::Operation::External(script_name, _) = &mut expression.operation {
+            if let Some(name) = aliases.get(script_name) {
+                *script_name = name.to_string();
+            }
+        }
+        for variable in &mut expression.variables {
+            if variable.starts_with('"') {
+                continue;
+            }
+            if let Some((script_name, function_name)) = variables::function_reference(variable) {
+                if let Some(name) = aliases.get(&script_name) {
+                    *variable = format!("{}@{}", function_name, name);
+                }
+            }
+        }
+        match &expression.to {
+            structures::ExpressionTo::ToBlock(block)
+            | structures::ExpressionTo::Collect(_, _, block) => resolve(block, aliases),
+            _ => (),
+        }
+    }
+}
+/// Find calls to paths of loaded files, and add the module names of those paths to `aliases`
+fn resolve_paths(
+    body: &[Arc<RwLock<structures::Expression>>],
+    names: &HashMap<PathBuf, String>,
+    aliases: &mut HashMap<String, String>,
+) {
+    for expression in body {
+        let expression = expression.read().unwrap();
+        let mut script_names = Vec::new();
+        if let structures::Operation::External(script_name, _) = &expression.operation {
+            script_names.push(script_name.to_string());
+        }
+        for variable in &expression.variables {
+            if !variable.starts_with('"') {
+                if let Some((script_name, _)) = variables::function_reference(variable) {
+                    script_names.push(script_name);
+                }
+            }
+        }
+        for script_name in script_names {
+            if names.values().any(|name| *name == script_name) {
+                continue;
+            }
+            if let Some(name) = Path::new(&script_name)
+                .canonicalize()
+                .ok()
+                .and_then(|path| names.get(&path))
+            {
+                aliases.insert(script_name, name.to_string());
+            }
+        }
+        match &expression.to {
+            structures::ExpressionTo::ToBlock(block)
+            | structures::ExpressionTo::Collect(_, _, block) => {
+                resolve_paths(block, names, aliases)
+            }
+            _ => (),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn external(expression: &Arc<RwLock<structures::Expression>>) -> String {
+        match &expression.read().unwrap().operation {
+            structures::Operation::External(script_name, _) => script_name.to_string(),
+            _ => panic!("Expression should be an external call"),
+        }
+    }
+    #[test]
+    fn imports() {
+        let directory = std::env::temp_dir().join(format!("awsl-imports-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        std::fs::write(
+            directory.join("main.awsl"),
+            "import \"lib/a.awsl\" as a;\nimport \"lib/b.awsl\" as b;\nmain<>() {\n    x = f@a();\n    y = ref(b::g);\n}\n",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("lib").join("a.awsl"),
+            "module \"util\";\nimport \"b.awsl\" as b;\nf<>(){g@b();}",
+        )
+        .unwrap();
+        std::fs::write(directory.join("lib").join("b.awsl"), "g<>(){}").unwrap();
+        let mut modules = Modules::default();
+        let main_name = modules.load(&directory.join("main.awsl")).unwrap();
+        let b = directory
+            .join("lib")
+            .join("b.awsl")
+            .canonicalize()
+            .unwrap()
+            .display()
+            .to_string();
+        assert_eq!(modules.files().count(), 3);
+        let scripts = modules.finish();
+        let mut names = scripts.keys().collect::<Vec<&String>>();
+        names.sort();
+        assert_eq!(names.len(), 3);
+        assert!(scripts.contains_key("util"));
+        assert!(scripts.contains_key(&b));
+        let main = scripts[&main_name].get("main").unwrap();
+        assert_eq!(external(&main.process[0]), "util");
+        assert_eq!(
+            main.process[1].read().unwrap().variables,
+            vec![format!("g@{}", b)]
+        );
+        std::fs::write(
+            directory.join("lib").join("b.awsl"),
+            "import \"../main.awsl\" as main;\ng<>(){}",
+        )
+        .unwrap();
+        std::fs::write(directory.join("lib").join("a.awsl"), "f<>(){g@b()}").unwrap();
+        let mut modules = Modules::default();
+        let error = modules.load(&directory.join("main.awsl")).unwrap_err();
+        assert_eq!(error.kind, Kind::Invalid);
+        // Files that fail to parse are still watched by `run --watch`
+        assert_eq!(modules.files().count(), 2);
+        std::fs::write(
+            directory.join("lib").join("a.awsl"),
+            "module \"util\";\nimport \"b.awsl\" as b;\nf<>(){g@b();}",
+        )
+        .unwrap();
+        let error = Modules::default()
+            .load(&directory.join("main.awsl"))
+            .unwrap_err();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(error.kind, Kind::Invalid);
+        assert!(error.message.starts_with("Import cycle: "));
+        assert!(error.message.ends_with("main.awsl"));
+    }
+    #[test]
+    fn module_names() {
+        let directory = std::env::temp_dir().join(format!("awsl-modules-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        std::fs::write(directory.join("lib").join("a.awsl"), "f<>(){}").unwrap();
+        std::fs::write(
+            directory.join("main.awsl"),
+            format!(
+                "main<>(){{f@{}();}}",
+                directory.join(".").join("lib").join("a.awsl").display()
+            ),
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("c.awsl"),
+            "#!/usr/bin/env awsl run\nmodule \"lib\";",
+        )
+        .unwrap();
+        std::fs::write(directory.join("d.awsl"), "module \"lib\";").unwrap();
+        let mut modules = Modules::default();
+        let a = modules.load(&directory.join("lib").join("a.awsl")).unwrap();
+        let same = modules
+            .load(&directory.join("lib").join(".").join("a.awsl"))
+            .unwrap();
+        assert_eq!(a, same);
+        let main_name = modules.load(&directory.join("main.awsl")).unwrap();
+        modules.load(&directory.join("c.awsl")).unwrap();
+        let error = modules.load(&directory.join("d.awsl")).unwrap_err();
+        assert!(error.message.starts_with("Module lib is declared by both "));
+        let error = modules.load(&directory.join("e.awsl")).unwrap_err();
+        assert_eq!(error.kind, Kind::NotFound);
+        let scripts = modules.finish();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let main = scripts[&main_name].get("main").unwrap();
+        assert_eq!(external(&main.process[0]), a);
+    }
+    #[test]
+    fn manifest_imports() {
+        let directory = std::env::temp_dir().join(format!("awsl-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        std::fs::create_dir_all(directory.join("vendor").join("util")).unwrap();
+        std::fs::write(
+            directory.join(manifest::FILE_NAME),
+            "[project]\nentry = \"main.awsl\"\npaths = [\"lib\"]\n[dependencies]\nutil = \"vendor/util\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("main.awsl"),
+            "import \"a.awsl\" as a;\nimport \"util\" as util;\nimport \"util/b.awsl\" as b;\nmain<>(){f@a();g@util();h@b();}",
+        )
+        .unwrap();
+        std::fs::write(directory.join("lib").join("a.awsl"), "module \"a\";f<>(){}").unwrap();
+        std::fs::write(
+            directory.join("vendor").join("util").join("lib.awsl"),
+            "module \"util\";g<>(){}",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("vendor").join("util").join("b.awsl"),
+            "module \"b\";h<>(){}",
+        )
+        .unwrap();
+        let manifest = manifest::Manifest::from_file(&directory.join(manifest::FILE_NAME)).unwrap();
+        let entry = manifest.entry.clone().unwrap();
+        let mut modules = Modules::with_manifest(manifest);
+        let main_name = modules.load(&entry).unwrap();
+        let scripts = modules.finish();
+        std::fs::remove_dir_all(&directory).unwrap();
+        let main = scripts[&main_name].get("main").unwrap();
+        let calls = main.process.iter().map(external).collect::<Vec<String>>();
+        assert_eq!(calls, vec!["a", "util", "b"]);
+    }
+}
@@ -0,0 +1,727 @@
+use super::error::{Error, Kind};
+use super::{check, exec, loader, manifest, structures, variables};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+/// Language server, speaking the Language Server Protocol over JSON-RPC
+///
+/// Every open document is loaded with its imports whenever it changes, and checked like
+/// `awsl check`. Parsed scripts don't keep positions, so positions are found again in the text:
+/// diagnostics point at the definition of the function they're about (or at the start of the
+/// document), and requests look at the word under the cursor.
+pub struct Server {
+    manifest: manifest::Manifest,
+    /// Text of every open document, by URI
+    documents: HashMap<String, String>,
+    builtins: HashMap<String, &'static str>,
+    shutdown: bool,
+}
+/// A document loaded with its imports
+struct Analysis {
+    /// Module name of the document, or why it can't be loaded
+    module: Result<String, Error>,
+    /// Module name of every alias imported by the document
+    aliases: HashMap<String, String>,
+    /// Canonical path of every loaded module
+    paths: HashMap<String, PathBuf>,
+    scripts: HashMap<String, structures::Script>,
+}
+/// Part of a line, in UTF-16 code units as the protocol counts them
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Span {
+    line: usize,
+    start: usize,
+    end: usize,
+}
+impl Span {
+    fn range(self) -> Value {
+        json!({
+            "start": {"line": self.line, "character": self.start},
+            "end": {"line": self.line, "character": self.end},
+        })
+    }
+}
+impl Server {
+    pub fn new(manifest: manifest::Manifest) -> Self {
+        Self {
+            manifest,
+            documents: HashMap::new(),
+            builtins: exec::builtin_docs(),
+            shutdown: false,
+        }
+    }
+    /// Answer messages from `input` until the client asks to exit, and return whether it asked
+    /// to shut down first
+    pub fn run(mut self, mut input: impl BufRead, mut output: impl Write) -> Result<bool, Error> {
+        while let Some(message) = read_message(&mut input)? {
+            let method = message["method"].as_str().unwrap_or_default();
+            let params = &message["params"];
+            if method == "exit" {
+                return Ok(self.shutdown);
+            }
+            match message.get("id") {
+                Some(id) => {
+                    let response = match self.request(method, params) {
+                        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        Err((code, error)) => json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": {"code": code, "message": error},
+                        }),
+                    };
+                    write_message(&mut output, &response)?;
+                }
+                None => {
+                    for notification in self.notification(method, params) {
+                        write_message(&mut output, &notification)?;
+                    }
+                }
+            }
+        }
+        Ok(self.shutdown)
+    }
+    fn request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        let position = || {
+            let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+            let line = params["position"]["line"].as_u64().unwrap_or_default() as usize;
+            let character = params["position"]["character"].as_u64().unwrap_or_default() as usize;
+            (uri, line, character)
+        };
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {"triggerCharacters": ["@", ":"]},
+                },
+                "serverInfo": {"name": "awsl", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => {
+                let (uri, line, character) = position();
+                Ok(self.definition(uri, line, character).unwrap_or(Value::Null))
+            }
+            "textDocument/hover" => {
+                let (uri, line, character) = position();
+                Ok(self.hover(uri, line, character).unwrap_or(Value::Null))
+            }
+            "textDocument/completion" => {
+                let (uri, ..) = position();
+                Ok(Value::Array(self.completion(uri)))
+            }
+            _ => Err((-32601, format!("Method not found: {}", method))),
+        }
+    }
+    /// Handle a notification, and return the notifications to send back
+    fn notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // The whole document is sent on every change
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didSave" => params["text"].as_str(),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        };
+        if let Some(text) = text {
+            self.documents.insert(uri.to_string(), text.to_string());
+        }
+        if !self.documents.contains_key(&uri) {
+            return Vec::new();
+        }
+        vec![publish(&uri, self.diagnostics(&uri))]
+    }
+    /// Load a document with its imports, using the text of open documents instead of their files
+    fn analyze(&self, uri: &str) -> Option<Analysis> {
+        let path = uri_path(uri)?.canonicalize().ok()?;
+        let mut modules = loader::Modules::with_manifest(self.manifest.clone());
+        for (uri, text) in &self.documents {
+            if let Some(path) = uri_path(uri).and_then(|path| path.canonicalize().ok()) {
+                modules.set_source(path, text.to_string());
+            }
+        }
+        // A bug in the parser shouldn't take the server down, so it's reported like an error
+        let module = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| modules.load(&path)))
+            .unwrap_or_else(|_| {
+                Err(Error::new(
+                    Kind::Invalid,
+                    "Unable to analyze the document, because the parser failed",
+                ))
+            });
+        let aliases = modules.aliases(&path).cloned().unwrap_or_default();
+        let paths = modules
+            .modules()
+            .map(|(path, module_name)| (module_name.to_string(), path.to_path_buf()))
+            .collect();
+        Some(Analysis {
+            module,
+            aliases,
+            paths,
+            scripts: modules.finish(),
+        })
+    }
+    /// Errors of the document, or the problems found by `check` in its functions
+    fn diagnostics(&self, uri: &str) -> Vec<Value> {
+        let analysis = match self.analyze(uri) {
+            Some(analysis) => analysis,
+            None => return Vec::new(),
+        };
+        let definitions = definitions(&self.documents[uri]);
+        let diagnostic = |function_name: Option<&str>, message: &str| {
+            let span = definitions
+                .iter()
+                .find(|(name, _)| Some(name.as_str()) == function_name)
+                .map_or(
+                    Span {
+                        line: 0,
+                        start: 0,
+                        end: 0,
+                    },
+                    |(_, span)| *span,
+                );
+            json!({"range": span.range(), "severity": 1, "source": "awsl", "message": message})
+        };
+        match &analysis.module {
+            Err(e) => {
+                // Errors in the document are prefixed with its path, and parse errors with the
+                // definition of the function, as in "PATH: In `main<r>()`: ..."
+                let message = uri_path(uri)
+                    .and_then(|path| path.canonicalize().ok())
+                    .and_then(|path| {
+                        e.message
+                            .strip_prefix(&format!("{}: ", path.display()))
+                            .map(str::to_string)
+                    })
+                    .unwrap_or_else(|| e.message.to_string());
+                let function_name = message
+                    .strip_prefix("In `")
+                    .and_then(|rest| rest.split('<').next());
+                vec![diagnostic(function_name, &message)]
+            }
+            Ok(module_name) => check::check(&analysis.scripts)
+                .iter()
+                .filter_map(|error| {
+                    // Problems are reported as "FUNCTION@MODULE: MESSAGE"
+                    let (location, message) = error.split_once(": ")?;
+                    let (function_name, location_module) = location.split_once('@')?;
+                    if location_module == module_name {
+                        Some(diagnostic(Some(function_name), message))
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+        }
+    }
+    /// Location of the function called as `function@module` (or `module::function`) under the
+    /// cursor
+    fn definition(&self, uri: &str, line: usize, character: usize) -> Option<Value> {
+        let word = word_at(self.documents.get(uri)?, line, character)?;
+        let (module_name, function_name) = variables::function_reference(&word)?;
+        let analysis = self.analyze(uri)?;
+        let module_name = analysis
+            .aliases
+            .get(&module_name)
+            .cloned()
+            .unwrap_or(module_name);
+        let path = analysis.paths.get(&module_name)?;
+        let text = self
+            .documents
+            .iter()
+            .find(|(uri, _)| {
+                uri_path(uri)
+                    .and_then(|path| path.canonicalize().ok())
+                    .as_ref()
+                    == Some(path)
+            })
+            .map(|(_, text)| text.to_string())
+            .or_else(|| std::fs::read_to_string(path).ok())?;
+        let (_, span) = definitions(&text)
+            .into_iter()
+            .find(|(name, _)| *name == function_name)?;
+        Some(json!({"uri": path_uri(path), "range": span.range()}))
+    }
+    /// Usage of the builtin, or signature of the function, under the cursor
+    fn hover(&self, uri: &str, line: usize, character: usize) -> Option<Value> {
+        let word = word_at(self.documents.get(uri)?, line, character)?;
+        let value = match variables::function_reference(&word) {
+            None => {
+                let doc = self.builtins.get(&word)?;
+                let (usage, description) = doc.split_once(": ").unwrap_or((doc, ""));
+                format!("```awsl\n{}\n```\n{}", usage, description)
+            }
+            Some((module_name, function_name)) => {
+                let analysis = self.analyze(uri)?;
+                let module_name = analysis
+                    .aliases
+                    .get(&module_name)
+                    .cloned()
+                    .unwrap_or(module_name);
+                let function = analysis.scripts.get(&module_name)?.get(&function_name)?;
+                format!(
+                    "```awsl\n{}{}\n```\nIn module `{}`",
+                    function.name,
+                    function.signature(),
+                    module_name
+                )
+            }
+        };
+        Some(json!({"contents": {"kind": "markdown", "value": value}}))
+    }
+    /// Every builtin, and every function loaded with the document (by the alias of its module,
+    /// if it's imported as one)
+    fn completion(&self, uri: &str) -> Vec<Value> {
+        let mut items = Vec::new();
+        let mut builtin_names = self.builtins.keys().collect::<Vec<&String>>();
+        builtin_names.sort();
+        for name in builtin_names {
+            let doc = self.builtins[name];
+            let (usage, description) = doc.split_once(": ").unwrap_or((doc, ""));
+            items.push(json!({
+                "label": name,
+                "kind": 3,
+                "detail": usage,
+                "documentation": description,
+            }));
+        }
+        let analysis = match self.analyze(uri) {
+            Some(analysis) => analysis,
+            None => return items,
+        };
+        let mut module_names = analysis.scripts.keys().collect::<Vec<&String>>();
+        module_names.sort();
+        for module_name in module_names {
+            let script = &analysis.scripts[module_name];
+            let module_alias = analysis
+                .aliases
+                .iter()
+                .find(|(_, name)| *name == module_name)
+                .map_or(module_name, |(alias, _)| alias);
+            let mut function_names = script.keys().collect::<Vec<&String>>();
+            function_names.sort();
+            for function_name in function_names {
+                items.push(json!({
+                    "label": format!("{}@{}", function_name, module_alias),
+                    "kind": 3,
+                    "detail": format!("{}{}", function_name, script[function_name].signature()),
+                }));
+            }
+        }
+        items
+    }
+}
+fn publish(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": {"uri": uri, "diagnostics": diagnostics},
+    })
+}
+/// Name and position of every function defined in a script
+fn definitions(text: &str) -> Vec<(String, Span)> {
+    let (shebang, _) = loader::split_shebang(text);
+    let mut result = Vec::new();
+    let (mut line, mut column) = (0, 0);
+    let mut depth = 0usize;
+    let mut quoted = false;
+    // Whether a definition may start at the next character
+    let mut boundary = true;
+    let mut name: Option<(String, Span)> = None;
+    for (offset, c) in text.char_indices() {
+        if offset < shebang.len() {
+        } else if c == '"' {
+            quoted = !quoted;
+            name = None;
+            boundary = false;
+        } else if quoted {
+        } else if c == '{' {
+            depth += 1;
+            name = None;
+        } else if c == '}' {
+            depth = depth.saturating_sub(1);
+            boundary = depth == 0;
+        } else if depth > 0 || c.is_whitespace() {
+        } else if c == ';' {
+            name = None;
+            boundary = true;
+        } else if c == '<' {
+            result.extend(name.take());
+            boundary = false;
+        } else {
+            if boundary {
+                let span = Span {
+                    line,
+                    start: column,
+                    end: column,
+                };
+                name = Some((String::new(), span));
+                boundary = false;
+            }
+            if let Some((name, span)) = &mut name {
+                name.push(c);
+                span.end = column + c.len_utf16();
+            }
+        }
+        if c == '\n' {
+            line += 1;
+            column = 0;
+        } else {
+            column += c.len_utf16();
+        }
+    }
+    result
+}
+/// Name of a function or variable at a position, such as `function@module`
+fn word_at(text: &str, line: usize, character: usize) -> Option<String> {
+    let chars = text.lines().nth(line)?.chars().collect::<Vec<char>>();
+    let is_word = |c: &char| c.is_alphanumeric() || "_@:./".contains(*c);
+    // Position in characters rather than UTF-16 code units
+    let mut position = 0;
+    let mut units = 0;
+    while position < chars.len() && units + chars[position].len_utf16() <= character {
+        units += chars[position].len_utf16();
+        position += 1;
+    }
+    let mut start = position;
+    while start > 0 && is_word(&chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = position;
+    while end < chars.len() && is_word(&chars[end]) {
+        end += 1;
+    }
+    let word = chars[start..end]
+        .iter()
+        .collect::<String>()
+        .trim_matches(':')
+        .to_string();
+    Some(word).filter(|word| !word.is_empty())
+}
+fn uri_path(uri: &str) -> Option<PathBuf> {
+    let mut rest = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .filter(|_| byte == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+fn path_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+/// Read a message framed by a `Content-Length` header, or `None` at the end of the input
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, Error> {
+    let invalid = |e: String| Error::new(Kind::Invalid, format!("Invalid message: {}", e));
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input
+            .read_line(&mut line)
+            .map_err(|e| invalid(e.to_string()))?
+            == 0
+        {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(
+                value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|e| invalid(e.to_string()))?,
+            );
+        }
+    }
+    let length = length.ok_or_else(|| invalid(String::from("missing Content-Length")))?;
+    let mut body = vec![0; length];
+    input
+        .read_exact(&mut body)
+        .map_err(|e| invalid(e.to_string()))?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid(e.to_string()))
+}
+fn write_message(output: &mut impl Write, message: &Value) -> Result<(), Error> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|e| Error::new(Kind::Output, format!("Unable to write a message: {}", e)))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn definition_positions() {
+        let text = "#!/usr/bin/env awsl run\nimport \"lib.awsl\" as lib;\nmain<r>(a) {\n    x = f@lib(\"}\");\n}\n  helper <> () {}";
+        assert_eq!(
+            definitions(text),
+            vec![
+                (
+                    String::from("main"),
+                    Span {
+                        line: 2,
+                        start: 0,
+                        end: 4
+                    }
+                ),
+                (
+                    String::from("helper"),
+                    Span {
+                        line: 5,
+                        start: 2,
+                        end: 8
+                    }
+                ),
+            ]
+        );
+        assert_eq!(word_at(text, 3, 11), Some(String::from("f@lib")));
+        assert_eq!(word_at(text, 3, 4), Some(String::from("x")));
+        assert_eq!(word_at(text, 4, 0), None);
+        let path = Path::new("/tmp/a b/c.awsl");
+        assert_eq!(path_uri(path), "file:///tmp/a%20b/c.awsl");
+        assert_eq!(uri_path(&path_uri(path)).unwrap(), path);
+    }
+    #[test]
+    fn session() {
+        let directory = std::env::temp_dir().join(format!("awsl-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("lib.awsl"),
+            "twice<r:Int>(x:Int) {\n    r = add(x, x);\n}\n",
+        )
+        .unwrap();
+        let main = directory.join("main.awsl");
+        std::fs::write(&main, "main<>(){}").unwrap();
+        let main_uri = path_uri(&main.canonicalize().unwrap());
+        let lib_uri = path_uri(&directory.join("lib.awsl").canonicalize().unwrap());
+        let text = "import \"lib.awsl\" as lib;\n\nmain<r>() {\n    r = twice@lib(\"a\");\n    print(r);\n}\n";
+        let position = |id: i64, method: &str, line: usize, character: usize| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": {
+                    "textDocument": {"uri": main_uri},
+                    "position": {"line": line, "character": character},
+                },
+            })
+        };
+        let messages = vec![
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "initialized", "params": {}}),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {"textDocument": {"uri": main_uri, "text": text}},
+            }),
+            position(2, "textDocument/definition", 3, 10),
+            position(3, "textDocument/hover", 4, 5),
+            position(4, "textDocument/completion", 4, 0),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": {"uri": main_uri},
+                    "contentChanges": [{"text": "main<r>() {\n    r = twice@lib(1)\n}\n"}],
+                },
+            }),
+            json!({"jsonrpc": "2.0", "id": 5, "method": "unknown", "params": {}}),
+            json!({"jsonrpc": "2.0", "id": 6, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        let shutdown = Server::new(manifest::Manifest::default())
+            .run(&input[..], &mut output)
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(shutdown);
+        let mut output = &output[..];
+        let mut responses = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            responses.push(message);
+        }
+        assert_eq!(responses.len(), 8);
+        assert_eq!(
+            responses[0]["result"]["capabilities"]["hoverProvider"],
+            true
+        );
+        assert_eq!(
+            responses[1]["params"]["diagnostics"],
+            json!([{
+                "range": Span {line: 2, start: 0, end: 4}.range(),
+                "severity": 1,
+                "source": "awsl",
+                "message": "Parameter `x` of function `twice` expects Int, found String",
+            }])
+        );
+        assert_eq!(
+            responses[2]["result"],
+            json!({"uri": lib_uri, "range": Span {line: 0, start: 0, end: 5}.range()})
+        );
+        assert!(responses[3]["result"]["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("print(value)"));
+        let labels = responses[4]["result"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["label"].as_str().unwrap())
+            .collect::<Vec<&str>>();
+        assert!(labels.contains(&"twice@lib"));
+        assert!(labels.contains(&"assert_eq"));
+        let diagnostic = &responses[5]["params"]["diagnostics"][0];
+        assert_eq!(
+            diagnostic["range"],
+            Span {
+                line: 0,
+                start: 0,
+                end: 4
+            }
+            .range()
+        );
+        assert!(diagnostic["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("In `main<r>()`: "));
+        assert_eq!(responses[6]["error"]["code"], -32601);
+        assert_eq!(responses[7]["result"], Value::Null);
+    }
+    #[test]
+    fn non_ascii_document() {
+        let directory =
+            std::env::temp_dir().join(format!("awsl-lsp-unicode-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let main = directory.join("main.awsl");
+        std::fs::write(&main, "main<>(){}").unwrap();
+        let main_uri = path_uri(&main.canonicalize().unwrap());
+        let text = "main<>() {\n    print(\"héllo ✓\"); print(\"🦀\");\n}\n";
+        let messages = vec![
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {"textDocument": {"uri": main_uri, "text": text}},
+            }),
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "textDocument/hover",
+                "params": {
+                    "textDocument": {"uri": main_uri},
+                    "position": {"line": 1, "character": 25},
+                },
+            }),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        let shutdown = Server::new(manifest::Manifest::default())
+            .run(&input[..], &mut output)
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(shutdown);
+        let mut output = &output[..];
+        let mut responses = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            responses.push(message);
+        }
+        assert_eq!(responses.len(), 4);
+        assert_eq!(responses[1]["params"]["diagnostics"], json!([]));
+        assert!(responses[2]["result"]["contents"]["value"]
+            .as_str()
+            .unwrap()
+            .contains("print(value)"));
+        assert_eq!(responses[3]["result"], Value::Null);
+    }
+    #[test]
+    fn assignment_without_variable() {
+        let directory =
+            std::env::temp_dir().join(format!("awsl-lsp-assignment-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let main = directory.join("main.awsl");
+        std::fs::write(&main, "main<>(){}").unwrap();
+        let main_uri = path_uri(&main.canonicalize().unwrap());
+        let messages = vec![
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {"textDocument": {"uri": main_uri, "text": "main<>(){\n=print(1);}"}},
+            }),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ];
+        let mut input = Vec::new();
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        let shutdown = Server::new(manifest::Manifest::default())
+            .run(&input[..], &mut output)
+            .unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(shutdown);
+        let mut output = &output[..];
+        let mut responses = Vec::new();
+        while let Some(message) = read_message(&mut output).unwrap() {
+            responses.push(message);
+        }
+        assert_eq!(responses.len(), 3);
+        let diagnostic = &responses[1]["params"]["diagnostics"][0];
+        assert_eq!(
+            diagnostic["range"]["start"],
+            json!({"line": 0, "character": 0})
+        );
+        assert_eq!(
+            diagnostic["message"],
+            "In `main<>()`: Expected a variable before `=`"
+        );
+    }
+}
@@ -1,47 +1,368 @@
+mod arguments;
+mod check;
+mod clock;
+mod compile;
+mod debug;
+mod error;
 mod exec;
+mod format;
 mod functions;
 mod kernel;
+mod loader;
+mod lsp;
+mod manifest;
+mod repl;
 mod structures;
+mod testing;
 mod variables;
+use error::{Error, Kind};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 fn main() {
     pretty_env_logger::init();
-    let cli_config = clap::App::new("awsl")
+    let cli_config = match app().get_matches_safe() {
+        Ok(cli_config) => cli_config,
+        Err(e) => match e.kind {
+            clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => e.exit(),
+            _ => Error::new(Kind::Usage, e.message).exit(),
+        },
+    };
+    let result = match cli_config.subcommand() {
+        ("run", Some(options)) => manifest().and_then(|manifest| run(options, manifest)),
+        ("check", Some(options)) => manifest().and_then(|manifest| check(options, manifest)),
+        ("fmt", Some(options)) => fmt(options),
+        ("test", Some(options)) => manifest().and_then(|manifest| test(options, manifest)),
+        ("repl", Some(options)) => manifest().and_then(|manifest| repl(options, manifest)),
+        ("compile", Some(options)) => manifest().and_then(|manifest| compile(options, manifest)),
+        ("lsp", Some(_)) => lsp(),
+        _ => unreachable!("A subcommand is required"),
+    };
+    match result {
+        Ok(Some(code)) => {
+            use std::io::Write;
+            std::io::stdout().flush().unwrap();
+            std::process::exit(code);
+        }
+        Ok(None) => (),
+        Err(e) => e.exit(),
+    }
+}
+/// Manifest of the current directory, or the default one if there is none
+fn manifest() -> Result<manifest::Manifest, Error> {
+    match manifest::Manifest::find(&std::env::current_dir().unwrap()) {
+        Ok(manifest) => Ok(manifest.unwrap_or_default()),
+        Err(e) => Err(Error::new(Kind::Invalid, e)),
+    }
+}
+fn app() -> clap::App<'static, 'static> {
+    clap::App::new("awsl")
         .version("0.9 Alpha")
         .author("moelife-coder <61054382+moelife-coder@users.noreply.github.com>")
         .about("A awsl-lang runtime executer")
-        .arg(
-            clap::Arg::with_name("run")
-                .takes_value(true)
-                .help("Name of the script that should be executed")
-                .short("r"),
+        .setting(clap::AppSettings::SubcommandRequiredElseHelp)
+        .after_help(
+            "Scripts default to the entry of awsl.toml.\n\n\
+             EXIT CODES:\n    \
+             2   invalid command line\n    \
+             3   script not found\n    \
+             4   invalid script, import or manifest\n    \
+             5   function not found\n    \
+             6   arguments don't match the function\n    \
+             7   check failed\n    \
+             8   scripts not formatted (fmt --check)\n    \
+             9   tests failed\n    \
+             10  output can't be written\n    \
+             11  result can't be an exit code (run --exit-code)\n    \
+             12  script failed while running\n\
+             Scripts may also exit with any code through `exit`.",
         )
         .arg(
             clap::Arg::with_name("load")
+                .global(true)
                 .short("l")
                 .takes_value(true)
                 .multiple(true)
-                .help("Name of the script that should be loaded"),
+                .number_of_values(1)
+                .help("Script that should be loaded as well"),
         )
         .arg(
-            clap::Arg::with_name("function_name")
-                .short("n")
-                .takes_value(true)
-                .multiple(false)
-                .help("Name of the function that should be executed"),
+            clap::Arg::with_name("ordered")
+                .global(true)
+                .long("ordered")
+                .help("Keep output of `=>` blocks in the order of their input"),
         )
         .arg(
-            clap::Arg::with_name("arguments")
-                .short("a")
+            clap::Arg::with_name("virtual_clock")
+                .global(true)
+                .long("virtual-clock")
+                .help("Run on a clock that skips ahead instead of waiting in `sleep`"),
+        )
+        .arg(
+            clap::Arg::with_name("seed")
+                .global(true)
+                .long("seed")
                 .takes_value(true)
-                .multiple(true)
-                .help("Arguments for the function"),
+                .validator(|seed| seed.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .help("Seed for random builtins and identifiers, to make runs reproducible"),
         )
-        .get_matches();
-    let script_file = cli_config.value_of("run").unwrap();
-    let loaded_script_file = match cli_config.values_of("load") {
-        None => Vec::new(),
-        Some(i) => i.collect(),
+        .subcommand(
+            clap::SubCommand::with_name("run")
+                .about("Run a function of a script")
+                .arg(
+                    clap::Arg::with_name("script")
+                        .help("Script that should be executed (`-` for stdin)"),
+                )
+                .arg(
+                    clap::Arg::with_name("function_name")
+                        .short("n")
+                        .takes_value(true)
+                        .multiple(false)
+                        .help("Name of the function that should be executed"),
+                )
+                .arg(
+                    clap::Arg::with_name("arguments")
+                        .short("a")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Argument for the function, by position or as `NAME=VALUE`. Parsed as the annotated type of its parameter (if any)"),
+                )
+                .arg(
+                    clap::Arg::with_name("arguments_file")
+                        .long("args-file")
+                        .takes_value(true)
+                        .help("JSON file with arguments for the function (`-` for stdin): an array by position, or an object by name"),
+                )
+                .arg(
+                    clap::Arg::with_name("json").long("json").help(
+                        "Parse arguments as JSON, and print the result of the function as JSON",
+                    ),
+                )
+                .arg(
+                    clap::Arg::with_name("debug")
+                        .long("debug")
+                        .help("Start paused, and step through packages from a prompt on stderr"),
+                )
+                .arg(
+                    clap::Arg::with_name("watch")
+                        .long("watch")
+                        .help("Run again whenever the script or any script it loads changes"),
+                )
+                .arg(
+                    clap::Arg::with_name("print")
+                        .long("print")
+                        .help("Print the result of the function as text"),
+                )
+                .arg(
+                    clap::Arg::with_name("exit_code")
+                        .long("exit-code")
+                        .help("Exit with the result of the function: an Int as it is, a Bool as 0 (true) or 1 (false), Nil as 0"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("check")
+                .about("Check calls between scripts against type annotations, without running them")
+                .arg(
+                    clap::Arg::with_name("scripts")
+                        .multiple(true)
+                        .help("Scripts that should be checked together"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("fmt")
+                .about("Format scripts in place")
+                .arg(
+                    clap::Arg::with_name("scripts")
+                        .multiple(true)
+                        .help("Scripts that should be formatted (`-` formats stdin to stdout)"),
+                )
+                .arg(
+                    clap::Arg::with_name("check")
+                        .long("check")
+                        .help("Only report scripts that aren't formatted"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("test")
+                .about("Run every `test_*` function of scripts")
+                .arg(
+                    clap::Arg::with_name("scripts")
+                        .multiple(true)
+                        .help("Scripts whose tests should be run"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("repl")
+                .about("Define functions and run expressions interactively"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("lsp")
+                .about("Run a language server that speaks the Language Server Protocol over stdio"),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("compile")
+                .about(
+                    "Check a script, and write it with its imports as formatted, resolved modules",
+                )
+                .arg(clap::Arg::with_name("script").help("Script that should be compiled"))
+                .arg(
+                    clap::Arg::with_name("output")
+                        .short("o")
+                        .takes_value(true)
+                        .default_value("build")
+                        .help("Directory the modules are written to"),
+                ),
+        )
+}
+/// How often `run --watch` looks for changes
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+fn run(cli_config: &clap::ArgMatches, manifest: manifest::Manifest) -> Result<Option<i32>, Error> {
+    let script_file = scripts(cli_config, "script", &manifest)?.remove(0);
+    let arguments_file = match cli_config.value_of("arguments_file") {
+        Some(arguments_file) => Some((arguments_file, read_arguments_file(arguments_file)?)),
+        None => None,
     };
+    let stdin = script_file == Path::new(loader::STDIN);
+    if stdin && matches!(&arguments_file, Some((loader::STDIN, _))) {
+        return Err(Error::new(
+            Kind::Usage,
+            "The script and the arguments can't both be read from stdin",
+        ));
+    }
+    if stdin && cli_config.is_present("watch") {
+        return Err(Error::new(
+            Kind::Usage,
+            "A script read from stdin can't be watched",
+        ));
+    }
+    let debug = cli_config.is_present("debug");
+    if debug && (stdin || matches!(&arguments_file, Some((loader::STDIN, _)))) {
+        return Err(Error::new(
+            Kind::Usage,
+            "The debugger reads commands from stdin, so the script and the arguments can't",
+        ));
+    }
+    if debug && cli_config.is_present("watch") {
+        return Err(Error::new(
+            Kind::Usage,
+            "--debug and --watch can't be used together",
+        ));
+    }
+    if cli_config.is_present("watch") {
+        watch(cli_config, manifest, &script_file, arguments_file);
+    }
+    let entry = start(
+        cli_config,
+        manifest,
+        &script_file,
+        arguments_file,
+        &mut BTreeSet::new(),
+    )?;
+    if debug {
+        entry.threads.send_message(kernel::Message::ExitGracefully);
+        let finished = debug::Debugger::new(&entry.threads, &entry.scripts)
+            .run(std::io::stdin().lock(), std::io::stderr());
+        if !finished {
+            entry.threads.stop();
+            return Ok(None);
+        }
+    }
+    report(cli_config, entry.threads.grace_stop(), &entry.result)
+}
+/// Run the entry function, and run it again whenever any loaded script changes
+///
+/// The kernel of the last run is detached before the scripts are loaded again, so a script blocked
+/// on input doesn't delay the restart. Errors are shown without stopping to watch.
+fn watch(
+    cli_config: &clap::ArgMatches,
+    manifest: manifest::Manifest,
+    script_file: &Path,
+    arguments_file: Option<(&str, String)>,
+) -> ! {
+    let mut files = BTreeSet::new();
+    loop {
+        files.insert(script_file.to_path_buf());
+        let mut running = match start(
+            cli_config,
+            manifest.clone(),
+            script_file,
+            arguments_file.clone(),
+            &mut files,
+        ) {
+            Ok(entry) => {
+                entry.threads.send_message(kernel::Message::ExitGracefully);
+                Some((entry.threads, entry.result))
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        };
+        let modified = modified_times(&files);
+        while modified_times(&files) == modified {
+            std::thread::sleep(WATCH_INTERVAL);
+            if running
+                .as_ref()
+                .is_some_and(|(threads, _)| threads.stopped())
+            {
+                let (threads, result) = running.take().unwrap();
+                match report(cli_config, threads.grace_stop(), &result) {
+                    Ok(Some(code)) => eprintln!("Exited with code {}", code),
+                    Ok(None) => (),
+                    Err(e) => eprintln!("{}", e),
+                }
+                eprintln!("Waiting for changes...");
+            }
+        }
+        if let Some((threads, _)) = running {
+            threads.detach();
+        }
+        eprintln!("Change detected, restarting");
+    }
+}
+/// Time every file was last modified, or `None` if it can't be read
+fn modified_times(files: &BTreeSet<PathBuf>) -> Vec<Option<std::time::SystemTime>> {
+    files
+        .iter()
+        .map(|file| {
+            std::fs::metadata(file)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        })
+        .collect()
+}
+fn read_arguments_file(arguments_file: &str) -> Result<String, Error> {
+    if arguments_file == loader::STDIN {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut content).map(|_| content)
+    } else {
+        std::fs::read_to_string(arguments_file)
+    }
+    .map_err(|e| {
+        Error::new(
+            Kind::NotFound,
+            format!("Unable to read {}: {}", arguments_file, e),
+        )
+    })
+}
+/// Kernel that executes the entry function of a script
+struct Entry {
+    threads: kernel::Kernel,
+    /// Receives the result of the entry function
+    result: std::sync::mpsc::Receiver<variables::Complex>,
+    /// Every loaded script, by module name
+    scripts: HashMap<String, structures::Script>,
+}
+/// Load the script, and start a kernel that executes the entry function
+///
+/// Every file that was read is added to `files`, even if loading fails.
+fn start(
+    cli_config: &clap::ArgMatches,
+    manifest: manifest::Manifest,
+    script_file: &Path,
+    arguments_file: Option<(&str, String)>,
+    files: &mut BTreeSet<PathBuf>,
+) -> Result<Entry, Error> {
     let function_name = match cli_config.value_of("function_name") {
         None => {
             log::warn!("No function name specified. Treated as \"main\"");
@@ -49,64 +370,332 @@ fn main() {
         }
         Some(i) => i,
     };
-    let threads = kernel::Kernel::new();
-    if !loaded_script_file.is_empty() {
-        for i in loaded_script_file {
-            let mut script = std::fs::read_to_string(i).unwrap();
-            let mut branches_count = 0;
-            script.retain(|c| {
-                (
-                    (!c.is_whitespace()) || (branches_count % 2 == 1),
-                    if c == '"' {
-                        branches_count += 1
-                    },
-                )
-                    .0
-            });
-            let script_structure = structures::new_script(&script);
-            threads.send_message(kernel::Message::NewScript(i.to_string(), script_structure));
-        }
-    }
-    let vars = match cli_config.values_of("arguments") {
+    let vars: Vec<&str> = match cli_config.values_of("arguments") {
         Some(i) => {
             log::trace!("Accepting values from command line interface - {:?}", i);
             i.collect()
         }
         None => Vec::new(),
     };
-    let mut main_script = std::fs::read_to_string(script_file).unwrap();
-    let mut branches_count = 0;
-    main_script.retain(|c| {
-        (
-            (!c.is_whitespace()) || (branches_count % 2 == 1),
-            if c == '"' {
-                branches_count += 1
-            },
-        )
-            .0
-    });
-    let main_script_structure = structures::new_script(&main_script);
-    let mut variables_primitive = Vec::new();
-    for i in vars {
-        variables_primitive.push(variables::Primitive::Literal(i.to_string()));
-    }
-    let packages = kernel::ExpressionPackage::from_function(
-        main_script_structure.get(function_name).unwrap(),
-        variables_primitive,
-    );
+    let mut modules = loader::Modules::with_manifest(manifest);
+    let main_module = load_into(cli_config, &mut modules, &[script_file.to_path_buf()]);
+    files.extend(modules.files().cloned());
+    let main_module = &main_module?[0];
+    let scripts = modules.finish();
+    let function = scripts[main_module].get(function_name).ok_or_else(|| {
+        Error::new(
+            Kind::FunctionNotFound,
+            format!(
+                "Function `{}` not found in {}",
+                function_name,
+                script_file.display()
+            ),
+        )
+    })?;
+    let json_mode = cli_config.is_present("json");
+    let mut function_arguments = arguments::Arguments::default();
+    for i in &vars {
+        function_arguments
+            .push(function, i, json_mode)
+            .map_err(|e| Error::new(Kind::Arguments, e))?;
+    }
+    if let Some((arguments_file, content)) = arguments_file {
+        function_arguments.push_json(&content).map_err(|e| {
+            Error::new(
+                Kind::Arguments,
+                format!("Invalid arguments in {}: {}", arguments_file, e),
+            )
+        })?;
+    }
+    let function_input = function_arguments
+        .bind(function)
+        .map_err(|e| Error::new(Kind::Arguments, e))?;
+    let (packages, result) = kernel::ExpressionPackage::from_function(function, function_input)
+        .map_err(|e| Error::new(Kind::Arguments, e))?;
+    let threads = kernel::Kernel::with_config(kernel_config(
+        cli_config,
+        vars.iter().map(|i| i.to_string()).collect(),
+    ));
+    for (script_name, script_structure) in &scripts {
+        threads.send_message(kernel::Message::NewScript(
+            script_name.to_string(),
+            std::sync::Arc::clone(script_structure),
+        ));
+    }
     for i in packages {
         threads.send_package(i);
     }
-    threads.grace_stop();
+    Ok(Entry {
+        threads,
+        result,
+        scripts,
+    })
+}
+/// Print the result of the entry function as asked, and return the exit code
+fn report(
+    cli_config: &clap::ArgMatches,
+    stop: Option<kernel::Stop>,
+    result: &std::sync::mpsc::Receiver<variables::Complex>,
+) -> Result<Option<i32>, Error> {
+    let exit_code = stop_code(stop)?;
+    // There is no result if the script called `exit`
+    let result = match (exit_code, result.try_recv()) {
+        (None, Ok(result)) => result,
+        _ => return Ok(exit_code),
+    };
+    if cli_config.is_present("json") {
+        println!("{}", result.to_json());
+    } else if cli_config.is_present("print")
+        && !matches!(
+            result,
+            variables::Complex::Primitive(variables::Primitive::Nil)
+        )
+    {
+        println!("{}", result);
+    }
+    if cli_config.is_present("exit_code") {
+        return result_code(&result).map(Some);
+    }
+    Ok(None)
+}
+/// Exit code of a function result, for `run --exit-code`
+fn result_code(result: &variables::Complex) -> Result<i32, Error> {
+    use std::convert::TryFrom;
+    match result {
+        variables::Complex::Primitive(variables::Primitive::Int(code))
+            if i32::try_from(*code).is_ok() =>
+        {
+            Ok(*code as i32)
+        }
+        variables::Complex::Primitive(variables::Primitive::Bool(success)) => {
+            Ok(if *success { 0 } else { 1 })
+        }
+        variables::Complex::Primitive(variables::Primitive::Nil) => Ok(0),
+        _ => Err(Error::new(
+            Kind::Result,
+            format!(
+                "Result {} ({}) can't be an exit code",
+                result,
+                variables::Type::of(result)
+            ),
+        )),
+    }
+}
+fn check(
+    cli_config: &clap::ArgMatches,
+    manifest: manifest::Manifest,
+) -> Result<Option<i32>, Error> {
+    let script_files = scripts(cli_config, "scripts", &manifest)?;
+    let (_, script_map) = load(cli_config, manifest, &script_files)?;
+    let errors = check::check(&script_map);
+    if errors.is_empty() {
+        Ok(None)
+    } else {
+        Err(Error::new(Kind::Check, errors.join("\n")))
+    }
+}
+fn fmt(cli_config: &clap::ArgMatches) -> Result<Option<i32>, Error> {
+    // The manifest is only needed for its entry, so a broken one doesn't stop formatting
+    let script_files = match cli_config.values_of("scripts") {
+        Some(scripts) => scripts.map(PathBuf::from).collect(),
+        None => scripts(cli_config, "scripts", &manifest()?)?,
+    };
+    let mut unformatted = Vec::new();
+    for script_file in script_files {
+        let stdin = script_file == Path::new(loader::STDIN);
+        let script = if stdin {
+            let mut script = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut script).map(|_| script)
+        } else {
+            std::fs::read_to_string(&script_file)
+        }
+        .map_err(|e| {
+            Error::new(
+                Kind::NotFound,
+                format!("Unable to read {}: {}", script_file.display(), e),
+            )
+        })?;
+        let formatted = format::format(&script);
+        if cli_config.is_present("check") {
+            if formatted != script {
+                unformatted.push(format!("{} is not formatted", script_file.display()));
+            }
+        } else if stdin {
+            print!("{}", formatted);
+        } else if formatted != script {
+            std::fs::write(&script_file, formatted).map_err(|e| {
+                Error::new(
+                    Kind::Output,
+                    format!("Unable to write {}: {}", script_file.display(), e),
+                )
+            })?;
+        }
+    }
+    if unformatted.is_empty() {
+        Ok(None)
+    } else {
+        Err(Error::new(Kind::Unformatted, unformatted.join("\n")))
+    }
+}
+fn test(cli_config: &clap::ArgMatches, manifest: manifest::Manifest) -> Result<Option<i32>, Error> {
+    let script_files = scripts(cli_config, "scripts", &manifest)?;
+    let (module_names, scripts) = load(cli_config, manifest, &script_files)?;
+    let start = std::time::Instant::now();
+    let tests = testing::find(&scripts, &module_names);
+    println!("running {} tests", tests.len());
+    let mut failures = Vec::new();
+    for (module_name, function_name) in &tests {
+        let outcome = testing::run(
+            &scripts,
+            module_name,
+            function_name,
+            kernel_config(cli_config, Vec::new()),
+        );
+        let duration = outcome.duration.as_secs_f64();
+        match outcome.failure {
+            None => println!("test {} ... ok ({:.3}s)", outcome.name, duration),
+            Some(failure) => {
+                println!("test {} ... FAILED ({:.3}s)", outcome.name, duration);
+                failures.push(format!("    {}: {}", outcome.name, failure));
+            }
+        }
+    }
+    if !failures.is_empty() {
+        println!("\nfailures:\n{}", failures.join("\n"));
+    }
+    println!(
+        "\ntest result: {}. {} passed; {} failed; finished in {:.3}s",
+        if failures.is_empty() { "ok" } else { "FAILED" },
+        tests.len() - failures.len(),
+        failures.len(),
+        start.elapsed().as_secs_f64()
+    );
+    if failures.is_empty() {
+        Ok(None)
+    } else {
+        Err(Error::new(
+            Kind::TestFailed,
+            format!("{} of {} tests failed", failures.len(), tests.len()),
+        ))
+    }
+}
+fn repl(cli_config: &clap::ArgMatches, manifest: manifest::Manifest) -> Result<Option<i32>, Error> {
+    let mut session = repl::Repl::new(kernel_config(cli_config, Vec::new()), manifest);
+    for i in cli_config.values_of("load").into_iter().flatten() {
+        session
+            .load(Path::new(i))
+            .map_err(|e| Error::new(Kind::Invalid, e))?;
+    }
+    stop_code(session.run(std::io::stdin().lock()))
+}
+fn lsp() -> Result<Option<i32>, Error> {
+    // Editors start the server on their own, so a broken manifest shouldn't keep it from running
+    let manifest = manifest().unwrap_or_else(|e| {
+        log::warn!("{}. The default manifest is used instead.", e);
+        manifest::Manifest::default()
+    });
+    let server = lsp::Server::new(manifest);
+    // The protocol asks for a failure if the client exits without shutting the server down
+    match server.run(std::io::stdin().lock(), std::io::stdout().lock())? {
+        true => Ok(None),
+        false => Ok(Some(1)),
+    }
+}
+fn compile(
+    cli_config: &clap::ArgMatches,
+    manifest: manifest::Manifest,
+) -> Result<Option<i32>, Error> {
+    let script_file = scripts(cli_config, "script", &manifest)?;
+    let (main_module, scripts) = load(cli_config, manifest, &script_file)?;
+    let errors = check::check(&scripts);
+    if !errors.is_empty() {
+        return Err(Error::new(Kind::Check, errors.join("\n")));
+    }
+    let paths = compile::compile(&scripts, Path::new(cli_config.value_of("output").unwrap()))?;
+    println!("{}", paths[&main_module[0]].display());
+    Ok(None)
+}
+/// Scripts given as `name`, or the entry of the manifest if there are none
+fn scripts(
+    cli_config: &clap::ArgMatches,
+    name: &str,
+    manifest: &manifest::Manifest,
+) -> Result<Vec<PathBuf>, Error> {
+    match (cli_config.values_of(name), &manifest.entry) {
+        (Some(scripts), _) => Ok(scripts.map(PathBuf::from).collect()),
+        (None, Some(entry)) => Ok(vec![entry.to_path_buf()]),
+        (None, None) => Err(Error::new(
+            Kind::Usage,
+            format!(
+                "No script given, and no entry found in {}",
+                manifest::FILE_NAME
+            ),
+        )),
+    }
+}
+/// Load the scripts given with `-l` and `script_files` with their imports, and return the module
+/// names of `script_files` with every loaded script
+fn load(
+    cli_config: &clap::ArgMatches,
+    manifest: manifest::Manifest,
+    script_files: &[PathBuf],
+) -> Result<(Vec<String>, HashMap<String, structures::Script>), Error> {
+    let mut modules = loader::Modules::with_manifest(manifest);
+    let module_names = load_into(cli_config, &mut modules, script_files)?;
+    Ok((module_names, modules.finish()))
+}
+/// Load the scripts given with `-l` and `script_files` into `modules`, and return the module names
+/// of `script_files`
+fn load_into(
+    cli_config: &clap::ArgMatches,
+    modules: &mut loader::Modules,
+    script_files: &[PathBuf],
+) -> Result<Vec<String>, Error> {
+    for i in cli_config.values_of("load").into_iter().flatten() {
+        modules.load(Path::new(i))?;
+    }
+    let mut module_names = Vec::new();
+    for script_file in script_files {
+        module_names.push(modules.load(script_file)?);
+    }
+    Ok(module_names)
+}
+/// Exit code requested by `exit`, or the error of a kernel that failed
+fn stop_code(stop: Option<kernel::Stop>) -> Result<Option<i32>, Error> {
+    match stop {
+        None => Ok(None),
+        Some(kernel::Stop::Exit(code)) => Ok(Some(code)),
+        Some(kernel::Stop::Fail(reason)) => Err(Error::new(Kind::Runtime, reason)),
+        Some(kernel::Stop::Mismatch(reason)) => Err(Error::new(Kind::Arguments, reason)),
+    }
+}
+/// Kernel options given on the command line
+fn kernel_config(cli_config: &clap::ArgMatches, arguments: Vec<String>) -> kernel::Config {
+    kernel::Config {
+        ordered_output: cli_config.is_present("ordered"),
+        arguments,
+        clock: if cli_config.is_present("virtual_clock") {
+            std::sync::Arc::new(clock::VirtualClock::default())
+        } else {
+            std::sync::Arc::new(clock::SystemClock)
+        },
+        seed: cli_config
+            .value_of("seed")
+            .map(|seed| seed.parse().unwrap()),
+        debug: cli_config.is_present("debug"),
+        recover: false,
+    }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
     #[test]
     fn exec() {
-        pretty_env_logger::init();
+        let _ = pretty_env_logger::try_init();
         let function = structures::Function {
+            name: String::from("main"),
             input: Vec::new(),
+            input_type: Vec::new(),
             process: vec![std::sync::Arc::new(std::sync::RwLock::new(
                 structures::Expression {
                     operation: structures::Operation::Builtin(String::from("print")),
@@ -115,23 +704,54 @@ mod tests {
                 },
             ))],
             output: None,
+            output_type: None,
         };
-        let threads = kernel::Kernel::new();
-        let package = kernel::ExpressionPackage::from_function(&function, Vec::new());
+        let threads = kernel::Kernel::default();
+        let (package, _) = kernel::ExpressionPackage::from_function(&function, Vec::new()).unwrap();
         for i in package {
             threads.send_package(i);
         }
         threads.grace_stop();
     }
     #[test]
+    fn stop_exit_code() {
+        let code = |stop| stop_code(stop).map_err(|e| (e.kind, e.message));
+        assert_eq!(code(None), Ok(None));
+        assert_eq!(code(Some(kernel::Stop::Exit(3))), Ok(Some(3)));
+        assert_eq!(
+            code(Some(kernel::Stop::Fail(String::from("failed")))),
+            Err((Kind::Runtime, String::from("failed")))
+        );
+        assert_eq!(
+            code(Some(kernel::Stop::Mismatch(String::from("mismatch")))),
+            Err((Kind::Arguments, String::from("mismatch")))
+        );
+    }
+    #[test]
+    fn result_exit_code() {
+        let code =
+            |primitive| result_code(&variables::Complex::Primitive(primitive)).map_err(|e| e.kind);
+        assert_eq!(code(variables::Primitive::Int(3)), Ok(3));
+        assert_eq!(code(variables::Primitive::Bool(true)), Ok(0));
+        assert_eq!(code(variables::Primitive::Bool(false)), Ok(1));
+        assert_eq!(code(variables::Primitive::Nil), Ok(0));
+        assert_eq!(code(variables::Primitive::Int(1 << 40)), Err(Kind::Result));
+        assert_eq!(
+            code(variables::Primitive::Literal(String::from("3"))),
+            Err(Kind::Result)
+        );
+    }
+    #[test]
     fn new_script() {
-        pretty_env_logger::init();
-        let threads = kernel::Kernel::new();
+        let _ = pretty_env_logger::try_init();
+        let threads = kernel::Kernel::default();
         let script = structures::new_script(
             "main<>(){print(\"abc\");stack123()=>{print(\"fgh\");};print(\"cda\");}",
-        );
-        let package =
-            kernel::ExpressionPackage::from_function(script.get("main").unwrap(), Vec::new());
+        )
+        .unwrap();
+        let (package, _) =
+            kernel::ExpressionPackage::from_function(script.get("main").unwrap(), Vec::new())
+                .unwrap();
         for i in package {
             threads.send_package(i);
         }
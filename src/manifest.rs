@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+pub const FILE_NAME: &str = "awsl.toml";
+/// Project settings from an `awsl.toml`
+///
+/// ```toml
+/// [project]
+/// entry = "src/main.awsl"
+/// paths = ["lib"]
+///
+/// [dependencies]
+/// util = { path = "vendor/util" }
+/// ```
+///
+/// Every path is relative to the directory of the manifest. Dependencies are local directories
+/// only, nothing is ever downloaded.
+#[derive(Clone, Debug, Default)]
+pub struct Manifest {
+    /// Script that `awsl run` executes
+    pub entry: Option<PathBuf>,
+    /// Directories searched for imports that aren't found next to the importing script
+    pub paths: Vec<PathBuf>,
+    /// Directory of every dependency, by the name it's imported with
+    pub dependencies: HashMap<String, PathBuf>,
+}
+impl Manifest {
+    /// Find the manifest in `directory` or any of its parents
+    pub fn find(directory: &Path) -> Result<Option<Self>, String> {
+        for directory in directory.ancestors() {
+            let path = directory.join(FILE_NAME);
+            if path.is_file() {
+                return Self::from_file(&path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+        Self::from_str(&content, path.parent().unwrap_or_else(|| Path::new(".")))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+    /// Parse a manifest, with paths relative to `root`
+    pub fn from_str(content: &str, root: &Path) -> Result<Self, String> {
+        let manifest = content.parse::<toml::Value>().map_err(|e| e.to_string())?;
+        let mut result = Self::default();
+        if let Some(project) = manifest.get("project") {
+            if let Some(entry) = project.get("entry") {
+                result.entry =
+                    Some(root.join(entry.as_str().ok_or("project.entry should be a string")?));
+            }
+            if let Some(paths) = project.get("paths") {
+                for path in paths.as_array().ok_or("project.paths should be an array")? {
+                    result.paths.push(
+                        root.join(
+                            path.as_str()
+                                .ok_or("project.paths should only contain strings")?,
+                        ),
+                    );
+                }
+            }
+        }
+        if let Some(dependencies) = manifest.get("dependencies") {
+            for (name, dependency) in dependencies
+                .as_table()
+                .ok_or("dependencies should be a table")?
+            {
+                let path = match dependency {
+                    toml::Value::String(path) => path,
+                    _ => match dependency.get("path").and_then(toml::Value::as_str) {
+                        Some(path) => path,
+                        None => {
+                            return Err(format!(
+                                "Dependency {} should have a path. Only local dependencies are supported.",
+                                name
+                            ))
+                        }
+                    },
+                };
+                result
+                    .dependencies
+                    .insert(name.to_string(), root.join(path));
+            }
+        }
+        Ok(result)
+    }
+    /// Entry script of a dependency directory: the entry of its own manifest, or `lib.awsl`
+    pub fn dependency_entry(directory: &Path) -> Result<PathBuf, String> {
+        let path = directory.join(FILE_NAME);
+        if path.is_file() {
+            if let Some(entry) = Self::from_file(&path)?.entry {
+                return Ok(entry);
+            }
+        }
+        Ok(directory.join("lib.awsl"))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse() {
+        let root = Path::new("/project");
+        let manifest = Manifest::from_str(
+            "[project]\nentry = \"src/main.awsl\"\npaths = [\"lib\"]\n\n[dependencies]\nutil = { path = \"vendor/util\" }\ntext = \"vendor/text\"\n",
+            root,
+        )
+        .unwrap();
+        assert_eq!(manifest.entry, Some(root.join("src/main.awsl")));
+        assert_eq!(manifest.paths, vec![root.join("lib")]);
+        assert_eq!(manifest.dependencies["util"], root.join("vendor/util"));
+        assert_eq!(manifest.dependencies["text"], root.join("vendor/text"));
+        let error = Manifest::from_str("[dependencies]\nutil = { version = \"1\" }\n", root);
+        assert_eq!(
+            error.unwrap_err(),
+            "Dependency util should have a path. Only local dependencies are supported."
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use super::{kernel, loader, manifest, structures, variables};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+/// Module of the functions defined in the session, which are called as `function@repl`
+pub const MODULE_NAME: &str = "repl";
+/// Variable that stores the result of an input ending with a call that isn't assigned
+const RESULT_VARIABLE: &str = "_";
+const HELP: &str = "\
+Enter function definitions, such as `double<r>(x){r=add(x,x);}`, or expressions, such as
+`a=double@repl(2);`. The result of an expression that isn't assigned is stored in `_`.
+
+:load PATH    Load a script and its imports
+:functions    List loaded functions
+:vars         Show variables of the session
+:help         Show this message
+:quit         Leave the session";
+/// Interactive session on a kernel that is kept alive between inputs
+pub struct Repl {
+    kernel: kernel::Kernel,
+    manifest: manifest::Manifest,
+    /// Every script registered with the kernel, by module name
+    scripts: HashMap<String, structures::Script>,
+    /// Functions defined in the session
+    functions: HashMap<String, structures::Function>,
+    /// Variables assigned by earlier inputs
+    variables: HashMap<String, variables::Complex>,
+    /// Whether the kernel has stopped, because a script called `exit`
+    ended: bool,
+}
+impl Repl {
+    /// Session on a kernel with `config`, which loads files through the search paths and
+    /// dependencies of `manifest`
+    ///
+    /// An input that fails is reported, and the session goes on without it.
+    pub fn new(config: kernel::Config, manifest: manifest::Manifest) -> Self {
+        Self {
+            kernel: kernel::Kernel::with_config(kernel::Config {
+                recover: true,
+                ..config
+            }),
+            manifest,
+            scripts: HashMap::new(),
+            functions: HashMap::new(),
+            variables: HashMap::new(),
+            ended: false,
+        }
+    }
+    /// Read inputs until the end of `input` or `:quit`, and return why the kernel stopped (if it
+    /// did), such as a script calling `exit`
+    pub fn run(mut self, input: impl BufRead) -> Option<kernel::Stop> {
+        let mut lines = input.lines();
+        let mut buffer = String::new();
+        loop {
+            print!("{}", if buffer.is_empty() { "> " } else { "... " });
+            std::io::stdout().flush().unwrap();
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+            if buffer.is_empty() && line.trim().starts_with(':') {
+                match self.command(line.trim()) {
+                    Ok(Some(message)) => println!("{}", message),
+                    Ok(None) => break,
+                    Err(e) => eprintln!("{}", e),
+                }
+                continue;
+            }
+            buffer.push_str(&line);
+            buffer.push('\n');
+            if !balanced(&buffer) {
+                continue;
+            }
+            match self.input(&buffer) {
+                Ok(Some(message)) => println!("{}", message),
+                Ok(None) => (),
+                Err(e) => eprintln!("{}", e),
+            }
+            buffer.clear();
+            if self.ended {
+                break;
+            }
+        }
+        self.kernel.grace_stop()
+    }
+    /// Run a `:` command, and return what should be shown, or `None` if the session should end
+    fn command(&mut self, command: &str) -> Result<Option<String>, String> {
+        let (name, argument) = match command.find(char::is_whitespace) {
+            Some(offset) => (&command[..offset], command[offset..].trim()),
+            None => (command, ""),
+        };
+        match name {
+            ":load" if !argument.is_empty() => {
+                let module_names = self.load(Path::new(argument))?;
+                Ok(Some(format!("Loaded {}", module_names.join(", "))))
+            }
+            ":functions" => Ok(Some(self.functions())),
+            ":vars" => {
+                let mut variable_names = self.variables.keys().collect::<Vec<&String>>();
+                variable_names.sort();
+                Ok(Some(
+                    variable_names
+                        .into_iter()
+                        .map(|variable_name| {
+                            format!("{} = {}", variable_name, self.variables[variable_name])
+                        })
+                        .collect::<Vec<String>>()
+                        .join("\n"),
+                ))
+            }
+            ":help" => Ok(Some(HELP.to_string())),
+            ":quit" => Ok(None),
+            _ => Err(format!("Unknown command {}. Try :help", command)),
+        }
+    }
+    /// Load a script with its imports, register them with the kernel, and return their module names
+    pub fn load(&mut self, path: &Path) -> Result<Vec<String>, String> {
+        let mut modules = loader::Modules::with_manifest(self.manifest.clone());
+        modules.load(path).map_err(|e| e.message)?;
+        let mut module_names = Vec::new();
+        for (module_name, script) in modules.finish() {
+            if self.scripts.contains_key(&module_name) {
+                continue;
+            }
+            self.kernel.send_message(kernel::Message::NewScript(
+                module_name.to_string(),
+                std::sync::Arc::clone(&script),
+            ));
+            self.scripts.insert(module_name.to_string(), script);
+            module_names.push(module_name);
+        }
+        module_names.sort();
+        Ok(module_names)
+    }
+    /// Signature of every loaded function, such as `double@repl<r>(x:Int)`
+    fn functions(&self) -> String {
+        let mut signatures = Vec::new();
+        for (module_name, script) in &self.scripts {
+            for function in script.values() {
+                signatures.push(format!(
+                    "{}@{}{}",
+                    function.name,
+                    module_name,
+                    function.signature()
+                ));
+            }
+        }
+        signatures.sort();
+        signatures.join("\n")
+    }
+    /// Define the functions of `input`, or run it as expressions. Returns what should be shown.
+    fn input(&mut self, input: &str) -> Result<Option<String>, String> {
+        let input = loader::strip_whitespace(input);
+        if input.is_empty() {
+            return Ok(None);
+        }
+        // Expressions only have a `{` after `=>`, definitions start with `NAME<RESULT>(VARIABLE){`
+        let is_definition = match input.find('{') {
+            Some(offset) => !input[..offset].contains('='),
+            None => false,
+        };
+        if is_definition {
+            let function_names = self.define(&input)?;
+            Ok(Some(format!("Defined {}", function_names.join(", "))))
+        } else {
+            Ok(self.evaluate(&input)?.map(|result| result.to_string()))
+        }
+    }
+    /// Add functions to the session (replacing those of the same name), and return their names
+    pub fn define(&mut self, definition: &str) -> Result<Vec<String>, String> {
+        let script = structures::new_script(&loader::strip_whitespace(definition))?;
+        let mut function_names = script.keys().cloned().collect::<Vec<String>>();
+        function_names.sort();
+        for function in script.values() {
+            self.functions
+                .insert(function.name.to_string(), function.clone());
+        }
+        let script = std::sync::Arc::new(self.functions.clone());
+        self.kernel.send_message(kernel::Message::ReplaceScript(
+            MODULE_NAME.to_string(),
+            std::sync::Arc::clone(&script),
+        ));
+        self.scripts.insert(MODULE_NAME.to_string(), script);
+        Ok(function_names)
+    }
+    /// Run expressions with the variables of the session, and return the value of the last one
+    ///
+    /// The value is `None` if the last expression runs a block, or if its value is `Nil`.
+    pub fn evaluate(&mut self, expressions: &str) -> Result<Option<variables::Complex>, String> {
+        let mut expressions = loader::strip_whitespace(expressions);
+        if !expressions.ends_with(';') {
+            expressions.push(';');
+        }
+        let script = structures::new_script(&format!("{}<>(){{{}}}", MODULE_NAME, expressions))?;
+        let body = script[MODULE_NAME].process.clone();
+        let result = match body.last() {
+            Some(expression) => {
+                let mut expression = expression.write().unwrap();
+                match &expression.to {
+                    structures::ExpressionTo::ToVar(variable_name) => {
+                        Some(variable_name.to_string())
+                    }
+                    structures::ExpressionTo::Nil => {
+                        expression.to =
+                            structures::ExpressionTo::ToVar(RESULT_VARIABLE.to_string());
+                        Some(RESULT_VARIABLE.to_string())
+                    }
+                    _ => None,
+                }
+            }
+            None => None,
+        };
+        let (packages, receiver) =
+            kernel::ExpressionPackage::from_session(body, self.variables.clone());
+        for package in packages {
+            self.kernel.send_package(package);
+        }
+        // The kernel drops the packages if the input fails, or if a script calls `exit`
+        self.variables = match receiver.recv() {
+            Ok(variables) => variables,
+            Err(_) => match self.kernel.failure() {
+                Some(kernel::Stop::Fail(reason)) | Some(kernel::Stop::Mismatch(reason)) => {
+                    return Err(reason)
+                }
+                _ => {
+                    self.ended = true;
+                    return Err(String::from("The session has ended"));
+                }
+            },
+        };
+        Ok(result
+            .and_then(|variable_name| self.variables.get(&variable_name))
+            .filter(|value| {
+                !matches!(
+                    value,
+                    variables::Complex::Primitive(variables::Primitive::Nil)
+                )
+            })
+            .cloned())
+    }
+}
+/// Whether every `(` and `{` outside of strings has been closed
+fn balanced(input: &str) -> bool {
+    let mut depth = 0;
+    let mut in_string = false;
+    for c in input.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '(' | '{' if !in_string => depth += 1,
+            ')' | '}' if !in_string => depth -= 1,
+            _ => (),
+        }
+    }
+    depth <= 0
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn session() {
+        let _ = pretty_env_logger::try_init();
+        let mut repl = Repl::new(kernel::Config::default(), manifest::Manifest::default());
+        assert_eq!(
+            repl.define("double<r:Int>(x:Int){r=add(x,x);}").unwrap(),
+            vec!["double"]
+        );
+        let result = repl.evaluate("a=double@repl(21);").unwrap();
+        assert_eq!(result.unwrap().to_string(), "42");
+        let result = repl.evaluate("add(a,1)").unwrap();
+        assert_eq!(result.unwrap().to_string(), "43");
+        assert_eq!(repl.variables["_"].to_string(), "43");
+        assert_eq!(repl.functions(), "double@repl<r:Int>(x:Int)");
+        assert!(repl.evaluate("print(a)").unwrap().is_none());
+        assert!(balanced("f<>(){a=\"{\";}"));
+        assert!(!balanced("stack123()=>{"));
+        assert_eq!(repl.kernel.grace_stop(), None);
+    }
+    #[test]
+    fn failed_input() {
+        let _ = pretty_env_logger::try_init();
+        let mut repl = Repl::new(kernel::Config::default(), manifest::Manifest::default());
+        repl.evaluate("a=add(1,2);").unwrap();
+        assert_eq!(
+            repl.evaluate("print(missing);").unwrap_err(),
+            "Variable `missing` is not defined"
+        );
+        assert_eq!(
+            repl.evaluate("b=add(a,1);stack123()=>{foo(this);};")
+                .unwrap_err(),
+            "Unknown function `foo`"
+        );
+        assert!(!repl.variables.contains_key("b"));
+        let result = repl.evaluate("add(a,1)").unwrap();
+        assert_eq!(result.unwrap().to_string(), "4");
+        let input = "c=add(a,2);\nprint(missing);\nexit(c);\nprint(\"unreachable\");\n";
+        assert_eq!(repl.run(input.as_bytes()), Some(kernel::Stop::Exit(5)));
+    }
+}
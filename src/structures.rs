@@ -1,53 +1,66 @@
+use super::variables;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 pub type Script = Arc<HashMap<String, Function>>;
 /// Create a script from a `&str`
 ///
 /// Please delete all whitespace before creating it
-pub fn new_script(script_code: &str) -> Script {
+pub fn new_script(script_code: &str) -> Result<Script, String> {
+    // Byte offset of the definition or the block that is being read
     let mut current_offset = 0;
-    let mut added_offset = 0;
     let mut current_beacket = 0;
     let mut definition = "";
     let mut block;
     let mut function: HashMap<String, Function> = HashMap::new();
-    while script_code.len() > current_offset + added_offset {
-        added_offset += 1;
-        if &script_code[current_offset + added_offset - 1..current_offset + added_offset] == "{" {
+    for (offset, c) in script_code.char_indices() {
+        if c == '{' {
             current_beacket += 1;
             if current_beacket == 1 {
-                definition = &script_code[current_offset..added_offset + current_offset - 1];
-                current_offset += added_offset;
-                added_offset = 0;
+                definition = &script_code[current_offset..offset];
+                current_offset = offset + 1;
+            }
+        } else if c == '}' {
+            if current_beacket == 0 {
+                // Whitespace is gone, so the `}` is located by the function it follows
+                return Err(if definition.is_empty() {
+                    String::from("Unmatched `}` before the first function")
+                } else {
+                    format!("In `{}`: Unmatched `}}` after the function", definition)
+                });
             }
-        } else if &script_code[current_offset + added_offset - 1..current_offset + added_offset]
-            == "}"
-        {
             current_beacket -= 1;
             if current_beacket == 0 {
-                block = &script_code[current_offset..added_offset + current_offset - 1];
+                block = &script_code[current_offset..offset];
                 let block_into_function = Function::from_str(
                     definition,
-                    Expression::from_char(&block.chars().collect::<Vec<char>>()[..]),
+                    Expression::from_char(&block.chars().collect::<Vec<char>>()[..])
+                        .map_err(|e| format!("In `{}`: {}", definition, e))?,
                 )
-                .unwrap();
-                function.insert(block_into_function.0.to_string(), block_into_function.1);
-                current_offset += added_offset;
-                added_offset = 0;
+                .map_err(|e| format!("In `{}`: {}", definition, e))?;
+                function.insert(block_into_function.name.to_string(), block_into_function);
+                current_offset = offset + 1;
             }
         }
     }
-    Arc::new(function)
+    if current_beacket != 0 {
+        return Err(format!("In `{}`: `{{` is never closed", definition));
+    }
+    Ok(Arc::new(function))
 }
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 /// `Function` is a set of process instruction (`Expression`) and variables definition (both input and outbut)
 pub struct Function {
+    pub name: String,
     /// Function's input variable name
     pub input: Vec<String>,
+    /// Type annotation of each input variable (if any)
+    pub input_type: Vec<Option<variables::Type>>,
     /// How to process input variable
     pub process: Vec<Arc<RwLock<Expression>>>,
     /// Function's output variable (if any)
     pub output: Option<String>,
+    /// Type annotation of the output variable (if any)
+    pub output_type: Option<variables::Type>,
 }
 impl Function {
     /// Create a function from string and `CommandBlock`
@@ -59,10 +72,12 @@ impl Function {
     /// - `NAME` should not contain `<`,
     /// - `RESULT` should not contain `>`
     /// - space between `RESULT` and `VARIABLE` should be exactly two character long ( For example, `<(`)
+    ///
+    /// `RESULT` and each `VARIABLE` may be annotated with a type, such as `sum<total:Int>(a:Int,b:Int)`
     fn from_str(
         function_definition: &str,
         function_content: Vec<Expression>,
-    ) -> Result<(&str, Self), &'static str> {
+    ) -> Result<Self, &'static str> {
         if function_definition.find('<').is_none() {
             return Err("Unable to find '<' in function definition");
         } else if function_definition[function_definition.find('<').unwrap()..]
@@ -71,6 +86,13 @@ impl Function {
         {
             return Err("Unable to find '>' after function name definition");
         }
+        match (
+            function_definition.rfind('('),
+            function_definition.rfind(')'),
+        ) {
+            (Some(open), Some(close)) if open < close => (),
+            _ => return Err("Unable to find '(' and ')' around function parameters"),
+        }
         let name = &function_definition[..function_definition.find('<').unwrap()];
         let result = &function_definition[function_definition.find('<').unwrap() + 1
             ..function_definition[function_definition.find('<').unwrap()..]
@@ -83,25 +105,93 @@ impl Function {
         for expression in function_content {
             process.push(Arc::new(RwLock::new(expression)));
         }
-        Ok((
-            name,
-            Self {
-                input: if variables.is_empty() {
-                    Vec::new()
-                } else {
-                    variables
-                        .split(',')
-                        .map(|s| s.to_string())
-                        .collect::<Vec<String>>()
-                },
-                process,
-                output: if result.is_empty() {
-                    None
-                } else {
-                    Some(result.to_string())
-                },
-            },
-        ))
+        let mut input = Vec::new();
+        let mut input_type = Vec::new();
+        if !variables.is_empty() {
+            for variable in variables.split(',') {
+                let (variable_name, variable_type) = Self::annotation(variable)?;
+                input.push(variable_name);
+                input_type.push(variable_type);
+            }
+        }
+        let (output, output_type) = if result.is_empty() {
+            (None, None)
+        } else {
+            let (result_name, result_type) = Self::annotation(result)?;
+            (Some(result_name), result_type)
+        };
+        Ok(Self {
+            name: name.to_string(),
+            input,
+            input_type,
+            process,
+            output,
+            output_type,
+        })
+    }
+    /// Split `NAME[:TYPE]` into the name and its type
+    fn annotation(variable: &str) -> Result<(String, Option<variables::Type>), &'static str> {
+        match variable.find(':') {
+            Some(offset) => Ok((
+                variable[..offset].to_string(),
+                Some(variables::Type::from_str(&variable[offset + 1..])?),
+            )),
+            None => Ok((variable.to_string(), None)),
+        }
+    }
+    /// Result and parameters with their annotations, such as `<total:Int>(a:Int,b)`
+    pub fn signature(&self) -> String {
+        let annotated =
+            |variable_name: &str, variable_type: &Option<variables::Type>| match variable_type {
+                Some(variable_type) => format!("{}:{}", variable_name, variable_type),
+                None => variable_name.to_string(),
+            };
+        let output = match &self.output {
+            Some(output) => annotated(output, &self.output_type),
+            None => String::new(),
+        };
+        let input = self
+            .input
+            .iter()
+            .zip(&self.input_type)
+            .map(|(variable_name, variable_type)| annotated(variable_name, variable_type))
+            .collect::<Vec<String>>();
+        format!("<{}>({})", output, input.join(","))
+    }
+    /// Check the number and the types of arguments passed to the function
+    pub fn check_input(&self, input: &[variables::Complex]) -> Result<(), String> {
+        if input.len() != self.input.len() {
+            return Err(format!(
+                "Function `{}` expects {} arguments, found {}",
+                self.name,
+                self.input.len(),
+                input.len()
+            ));
+        }
+        for ((variable_name, variable_type), value) in
+            self.input.iter().zip(&self.input_type).zip(input)
+        {
+            if let Some(variable_type) = variable_type {
+                let value_type = variables::Type::of(value);
+                if value_type != *variable_type {
+                    return Err(format!(
+                        "Parameter `{}` of function `{}` expects {}, found {}",
+                        variable_name, self.name, variable_type, value_type
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+/// Source of the function, without whitespace
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}{}{{", self.name, self.signature())?;
+        for expression in &self.process {
+            write!(f, "{}", expression.read().unwrap())?;
+        }
+        write!(f, "}}")
     }
 }
 #[derive(Debug)]
@@ -114,10 +204,11 @@ pub struct Expression {
     pub to: ExpressionTo,
 }
 impl Expression {
-    pub fn from_char(script_code: &[char]) -> Vec<Self> {
+    pub fn from_char(script_code: &[char]) -> Result<Vec<Self>, String> {
         // Grammar:
         // VARIABLE = COMMAND;
         // COMMAND => {ANOTHERBLOCK}
+        // VARIABLE = COMMAND => <RESULT> {ANOTHERBLOCK}
         // COMMAND;
         let mut self_vec: Vec<Self> = Vec::new();
         let mut command_detail = String::new();
@@ -127,56 +218,141 @@ impl Expression {
             while (script_code[char_offset] != '=') && (script_code[char_offset] != ';') {
                 command_detail.push(script_code[char_offset]);
                 char_offset += 1;
+                if char_offset == script_code.len() {
+                    return Err(format!("Expected `;` after `{}`", command_detail));
+                }
             }
             if script_code[char_offset] == '=' {
-                if script_code[char_offset + 1] == '>' {
+                if script_code.get(char_offset + 1) == Some(&'>') {
                     // COMMAND => {ANOTHERBOCK}
-                    let mut code_offset = 2;
+                    let mut block_start = char_offset + 2;
+                    let mut result = None;
+                    if script_code.get(block_start) == Some(&'<') {
+                        // VARIABLE = COMMAND => <RESULT> {ANOTHERBLOCK}
+                        let result_end = block_start
+                            + script_code[block_start..]
+                                .iter()
+                                .position(|c| *c == '>')
+                                .ok_or_else(|| {
+                                    format!("Expected `>` after `{}=><`", command_detail)
+                                })?;
+                        result = Some(
+                            script_code[block_start + 1..result_end]
+                                .iter()
+                                .collect::<String>(),
+                        );
+                        block_start = result_end + 1;
+                    }
+                    if script_code.get(block_start) != Some(&'{') {
+                        return Err(format!("Expected `{{` after `{}=>`", command_detail));
+                    }
+                    let mut code_offset = 1;
                     let mut branches_count = 1;
                     while branches_count != 0 {
-                        if script_code[char_offset + 1 + code_offset] == '{' {
-                            branches_count += 1;
-                        } else if script_code[char_offset + 1 + code_offset] == '}' {
-                            branches_count -= 1;
+                        match script_code.get(block_start + code_offset) {
+                            Some('{') => branches_count += 1,
+                            Some('}') => branches_count -= 1,
+                            Some(_) => (),
+                            None => {
+                                return Err(format!(
+                                    "`{{` after `{}=>` is never closed",
+                                    command_detail
+                                ))
+                            }
                         }
                         code_offset += 1;
                     }
                     let mut to_vec = Vec::new();
-                    for i in
-                        Self::from_char(&script_code[char_offset + 3..char_offset + code_offset])
-                    {
+                    for i in Self::from_char(
+                        &script_code[block_start + 1..block_start + code_offset - 1],
+                    )? {
                         to_vec.push(Arc::new(RwLock::new(i)));
                     }
-                    to = ExpressionTo::ToBlock(to_vec);
-                    char_offset += code_offset - 1;
+                    to = match (to, result) {
+                        (ExpressionTo::ToVar(variable), Some(result)) => {
+                            ExpressionTo::Collect(variable, result, to_vec)
+                        }
+                        _ => ExpressionTo::ToBlock(to_vec),
+                    };
+                    char_offset = block_start + code_offset - 2;
                 } else {
+                    if command_detail.is_empty() {
+                        return Err(String::from("Expected a variable before `=`"));
+                    }
+                    if let ExpressionTo::ToVar(variable) = &to {
+                        return Err(format!(
+                            "Expected a call after `{}=`, found `{}=`",
+                            variable, command_detail
+                        ));
+                    }
                     to = ExpressionTo::ToVar(command_detail);
                     command_detail = String::new();
                     char_offset -= 1;
                 }
                 char_offset += 1;
             } else {
-                let op = &command_detail[..command_detail.find('(').unwrap()];
-                let variable = &command_detail
-                    [command_detail.find('(').unwrap() + 1..command_detail.rfind(')').unwrap()];
+                let (open, close) = match (command_detail.find('('), command_detail.rfind(')')) {
+                    (Some(open), Some(close)) if open < close => (open, close),
+                    _ => {
+                        return Err(format!(
+                            "Expected a call such as `command(arguments)`, found `{}`",
+                            command_detail
+                        ))
+                    }
+                };
+                let op = &command_detail[..open];
+                let variable = &command_detail[open + 1..close];
+                let variables = if variable.is_empty() {
+                    Vec::new()
+                } else {
+                    variable
+                        .split(',')
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>()
+                };
+                if let Some(variable) = variables
+                    .iter()
+                    .find(|v| v.starts_with('"') && (v.len() < 2 || !v.ends_with('"')))
+                {
+                    return Err(format!("String `{}` is never closed", variable));
+                }
                 self_vec.push(Self {
-                    operation: Operation::from_str(&op).unwrap(),
+                    operation: Operation::from_str(op)?,
                     to,
-                    variables: if variable.is_empty() {
-                        Vec::new()
-                    } else {
-                        variable
-                            .split(',')
-                            .map(|s| s.to_string())
-                            .collect::<Vec<String>>()
-                    },
+                    variables,
                 });
                 command_detail = String::new();
                 to = ExpressionTo::Nil;
             }
             char_offset += 1;
         }
-        self_vec
+        Ok(self_vec)
+    }
+}
+/// Source of the expression, without whitespace
+impl std::fmt::Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let block = |f: &mut std::fmt::Formatter, block: &[Arc<RwLock<Expression>>]| {
+            write!(f, "{{")?;
+            for expression in block {
+                write!(f, "{}", expression.read().unwrap())?;
+            }
+            write!(f, "}}")
+        };
+        let call = format!("{}({})", self.operation, self.variables.join(","));
+        match &self.to {
+            ExpressionTo::ToVar(variable_name) => write!(f, "{}={}", variable_name, call)?,
+            ExpressionTo::ToBlock(to_block) => {
+                write!(f, "{}=>", call)?;
+                block(f, to_block)?;
+            }
+            ExpressionTo::Collect(variable_name, result, to_block) => {
+                write!(f, "{}={}=><{}>", variable_name, call, result)?;
+                block(f, to_block)?;
+            }
+            ExpressionTo::Nil => write!(f, "{}", call)?,
+        }
+        write!(f, ";")
     }
 }
 #[derive(Debug)]
@@ -184,26 +360,25 @@ pub enum Operation {
     Builtin(String),
     External(String, String),
 }
+impl std::fmt::Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Operation::Builtin(command) => write!(f, "{}", command),
+            Operation::External(parent, command) => write!(f, "{}@{}", command, parent),
+        }
+    }
+}
 impl Operation {
     //Parse a string into operation
     pub fn from_str(command_str: &str) -> Result<Self, &'static str> {
         // Command should be:
-        // COMMAND[@PARENT]
-        let mut parent: Option<String> = None;
-        let command = if command_str.find('@').is_some() {
-            //COMMAND@PARENT
-            parent = Some(command_str[command_str.find('@').unwrap() + 1..].to_string());
-            &command_str[..command_str.find('@').unwrap()]
-        } else {
-            //COMMAND
-            command_str
-        };
+        // COMMAND[@PARENT] or PARENT::COMMAND
         if command_str.is_empty() {
             return Err("Empty command instruction");
         }
-        match parent {
-            Some(par) => Ok(Self::External(par, command.to_string())),
-            None => Ok(Self::Builtin(command.to_string())),
+        match variables::function_reference(command_str) {
+            Some((parent, command)) => Ok(Self::External(parent, command)),
+            None => Ok(Self::Builtin(command_str.to_string())),
         }
     }
 }
@@ -211,5 +386,40 @@ impl Operation {
 pub enum ExpressionTo {
     ToVar(String),
     ToBlock(Vec<Arc<RwLock<Expression>>>),
+    /// Run the block for every element, and store the `RESULT` variable of each instance
+    /// into `VARIABLE`, as a stack in the order of the elements
+    Collect(String, String, Vec<Arc<RwLock<Expression>>>),
     Nil,
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            new_script("}main<>(){print(1);}").unwrap_err(),
+            "Unmatched `}` before the first function"
+        );
+        assert_eq!(
+            new_script("main<>(){print(1);}}other<>(){}").unwrap_err(),
+            "In `main<>()`: Unmatched `}` after the function"
+        );
+        assert_eq!(
+            new_script("main<>(){a=b=c();}").unwrap_err(),
+            "In `main<>()`: Expected a call after `a=`, found `b=`"
+        );
+        assert_eq!(
+            new_script("main<>(){=print(1);}").unwrap_err(),
+            "In `main<>()`: Expected a variable before `=`"
+        );
+        assert_eq!(
+            new_script("main<>(){print(\");}").unwrap_err(),
+            "In `main<>()`: String `\"` is never closed"
+        );
+        assert_eq!(
+            new_script("main<>(){print(\"a,b\");}").unwrap_err(),
+            "In `main<>()`: String `\"a` is never closed"
+        );
+        assert!(new_script("main<>(){a=c()=>{};w=c()=><y>{};}").is_ok());
+    }
+}
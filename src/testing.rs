@@ -0,0 +1,133 @@
+use super::{kernel, structures};
+use std::collections::HashMap;
+/// Prefix of the functions that `awsl test` runs
+pub const TEST_PREFIX: &str = "test_";
+/// Result of a test function
+pub struct Outcome {
+    /// `function@module` of the test
+    pub name: String,
+    /// Why the test failed, if it did
+    pub failure: Option<String>,
+    pub duration: std::time::Duration,
+}
+/// Every test function of `modules`, as (module name, function name), in order
+pub fn find(
+    scripts: &HashMap<String, structures::Script>,
+    modules: &[String],
+) -> Vec<(String, String)> {
+    let mut tests = Vec::new();
+    for module_name in modules {
+        let mut function_names = scripts[module_name]
+            .keys()
+            .filter(|function_name| function_name.starts_with(TEST_PREFIX))
+            .cloned()
+            .collect::<Vec<String>>();
+        function_names.sort();
+        for function_name in function_names {
+            tests.push((module_name.to_string(), function_name));
+        }
+    }
+    tests
+}
+/// Run a test function on its own kernel, with every script of `scripts` registered
+///
+/// A test fails if an assertion fails, a package panics, or it calls `exit` with a non-zero code.
+/// The failure only stops the kernel of the test.
+pub fn run(
+    scripts: &HashMap<String, structures::Script>,
+    module_name: &str,
+    function_name: &str,
+    config: kernel::Config,
+) -> Outcome {
+    let name = format!("{}@{}", function_name, module_name);
+    let start = std::time::Instant::now();
+    let (packages, _) = match kernel::ExpressionPackage::from_function(
+        &scripts[module_name][function_name],
+        Vec::new(),
+    ) {
+        Ok(function) => function,
+        Err(e) => {
+            return Outcome {
+                name,
+                failure: Some(e),
+                duration: start.elapsed(),
+            }
+        }
+    };
+    let threads = kernel::Kernel::with_config(config);
+    for (script_name, script) in scripts {
+        threads.send_message(kernel::Message::NewScript(
+            script_name.to_string(),
+            std::sync::Arc::clone(script),
+        ));
+    }
+    for package in packages {
+        threads.send_package(package);
+    }
+    let failure = match threads.grace_stop() {
+        Some(kernel::Stop::Exit(code)) if code != 0 => Some(format!("Exited with code {}", code)),
+        Some(kernel::Stop::Fail(reason)) | Some(kernel::Stop::Mismatch(reason)) => Some(reason),
+        _ => None,
+    };
+    Outcome {
+        name,
+        failure,
+        duration: start.elapsed(),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn run_tests() {
+        let _ = pretty_env_logger::try_init();
+        let mut scripts = HashMap::new();
+        scripts.insert(
+            String::from("lib"),
+            structures::new_script(
+                "test_pass<>(){b=json_parse(\"true\");assert(b);assert_eq(1,1);}test_exit<>(){exit(3);}test_input<>(a){}test_eq<>(){a=add(1,1);assert_eq(a,3,\"sum\");print(\"unreachable\");}test_fail<>(){stack123()=>{fail(\"in block\");};}test_undefined<>(){print(missing);}helper<>(){}",
+            )
+            .unwrap(),
+        );
+        let tests = find(&scripts, &[String::from("lib")]);
+        assert_eq!(
+            tests
+                .iter()
+                .map(|(_, function_name)| function_name.as_str())
+                .collect::<Vec<&str>>(),
+            vec![
+                "test_eq",
+                "test_exit",
+                "test_fail",
+                "test_input",
+                "test_pass",
+                "test_undefined"
+            ]
+        );
+        let failures = tests
+            .iter()
+            .map(|(module_name, function_name)| {
+                run(
+                    &scripts,
+                    module_name,
+                    function_name,
+                    kernel::Config::default(),
+                )
+                .failure
+            })
+            .collect::<Vec<Option<String>>>();
+        assert_eq!(
+            failures,
+            vec![
+                Some(String::from("sum: 2 (Int) != 3 (Int)")),
+                Some(String::from("Exited with code 3")),
+                Some(String::from("in block")),
+                Some(String::from(
+                    "Function `test_input` expects 1 arguments, found 0"
+                )),
+                None,
+                Some(String::from("Variable `missing` is not defined")),
+            ]
+        );
+    }
+}
@@ -1,19 +1,96 @@
-#[derive(Debug)]
+use std::collections::BTreeMap;
+#[derive(Debug, PartialEq)]
 pub enum Primitive {
     Nil,
     Literal(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    /// Reference to a function, as `(script, function)`
+    Function(String, String),
+    /// Raw bytes, shown as base64
+    Bytes(Vec<u8>),
 }
 impl Clone for Primitive {
     fn clone(&self) -> Self {
         match &self {
             Primitive::Nil => Self::Nil,
             Primitive::Literal(i) => Self::Literal(i.clone()),
+            Primitive::Int(i) => Self::Int(*i),
+            Primitive::Float(i) => Self::Float(*i),
+            Primitive::Bool(i) => Self::Bool(*i),
+            Primitive::Function(script, function) => {
+                Self::Function(script.clone(), function.clone())
+            }
+            Primitive::Bytes(i) => Self::Bytes(i.clone()),
         }
     }
 }
+impl std::fmt::Display for Primitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Primitive::Nil => Ok(()),
+            Primitive::Literal(i) => write!(f, "{}", i),
+            Primitive::Int(i) => write!(f, "{}", i),
+            Primitive::Float(i) => write!(f, "{}", i),
+            Primitive::Bool(i) => write!(f, "{}", i),
+            Primitive::Function(script, function) => write!(f, "{}@{}", function, script),
+            Primitive::Bytes(i) => write!(f, "{}", base64::encode(i)),
+        }
+    }
+}
+impl Primitive {
+    /// Parse a number literal (see `is_number`) from a script
+    pub fn from_number(argument: &str) -> Self {
+        match argument.parse() {
+            Ok(integer) => Self::Int(integer),
+            Err(_) => Self::Float(argument.parse().unwrap_or(f64::NAN)),
+        }
+    }
+    /// Value as an integer. Literals (such as command line arguments) are parsed.
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Primitive::Int(i) => Some(*i),
+            Primitive::Float(i) if i.fract() == 0.0 => Some(*i as i64),
+            Primitive::Literal(i) => i.parse().ok(),
+            _ => None,
+        }
+    }
+    fn from_json(value: serde_json::Value) -> Result<Self, String> {
+        Ok(match value {
+            serde_json::Value::Null => Self::Nil,
+            serde_json::Value::Bool(i) => Self::Bool(i),
+            serde_json::Value::Number(i) => match i.as_i64() {
+                Some(integer) => Self::Int(integer),
+                None => Self::Float(i.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(i) => Self::Literal(i),
+            serde_json::Value::Array(_) | serde_json::Value::Object(_) => {
+                return Err(String::from("Arrays and objects are not primitives"))
+            }
+        })
+    }
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Primitive::Nil => serde_json::Value::Null,
+            Primitive::Literal(i) => serde_json::Value::String(i.clone()),
+            Primitive::Int(i) => serde_json::Value::from(*i),
+            Primitive::Float(i) => serde_json::Number::from_f64(*i)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            Primitive::Bool(i) => serde_json::Value::Bool(*i),
+            Primitive::Function(..) | Primitive::Bytes(_) => {
+                serde_json::Value::String(self.to_string())
+            }
+        }
+    }
+}
+#[derive(Debug, PartialEq)]
 pub enum Complex {
     Primitive(Primitive),
-    Stack(Vec<Primitive>),
+    /// Elements may be collections themselves
+    Stack(Vec<Complex>),
+    Map(BTreeMap<String, Complex>),
 }
 impl Clone for Complex {
     fn clone(&self) -> Self {
@@ -21,11 +98,141 @@ impl Clone for Complex {
             Complex::Primitive(primitive_variable) => Self::Primitive(primitive_variable.clone()),
             Complex::Stack(stack_variables) => {
                 let mut stack_result = Vec::new();
-                for primitive_variable in stack_variables {
-                    stack_result.push(primitive_variable.clone());
+                for variable in stack_variables {
+                    stack_result.push(variable.clone());
                 }
                 Self::Stack(stack_result)
             }
+            Complex::Map(map_variables) => Self::Map(map_variables.clone()),
         }
     }
 }
+/// Primitives are shown as they are, collections as JSON
+impl std::fmt::Display for Complex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Complex::Primitive(primitive_variable) => write!(f, "{}", primitive_variable),
+            _ => write!(f, "{}", self.to_json()),
+        }
+    }
+}
+impl Complex {
+    /// Arrays become stacks, objects become maps, and `null` becomes `Nil`
+    pub fn from_json(value: serde_json::Value) -> Result<Self, String> {
+        Ok(match value {
+            serde_json::Value::Array(array) => Self::Stack(
+                array
+                    .into_iter()
+                    .map(Self::from_json)
+                    .collect::<Result<_, _>>()?,
+            ),
+            serde_json::Value::Object(object) => Self::Map(
+                object
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, Self::from_json(value)?)))
+                    .collect::<Result<_, String>>()?,
+            ),
+            value => Self::Primitive(Primitive::from_json(value)?),
+        })
+    }
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Complex::Primitive(primitive_variable) => primitive_variable.to_json(),
+            Complex::Stack(stack_variables) => {
+                serde_json::Value::Array(stack_variables.iter().map(Self::to_json).collect())
+            }
+            Complex::Map(map_variables) => serde_json::Value::Object(
+                map_variables
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.to_json()))
+                    .collect(),
+            ),
+        }
+    }
+}
+/// Type of a value, as written in annotations such as `a:Int`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Type {
+    Nil,
+    String,
+    Int,
+    Float,
+    Bool,
+    Function,
+    Bytes,
+    Stack,
+    Map,
+}
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+impl Type {
+    pub fn from_str(type_str: &str) -> Result<Self, &'static str> {
+        Ok(match type_str {
+            "Nil" => Self::Nil,
+            "String" => Self::String,
+            "Int" => Self::Int,
+            "Float" => Self::Float,
+            "Bool" => Self::Bool,
+            "Function" => Self::Function,
+            "Bytes" => Self::Bytes,
+            "Stack" => Self::Stack,
+            "Map" => Self::Map,
+            _ => return Err("Unknown type in annotation"),
+        })
+    }
+    pub fn of(value: &Complex) -> Self {
+        match value {
+            Complex::Primitive(Primitive::Nil) => Self::Nil,
+            Complex::Primitive(Primitive::Literal(_)) => Self::String,
+            Complex::Primitive(Primitive::Int(_)) => Self::Int,
+            Complex::Primitive(Primitive::Float(_)) => Self::Float,
+            Complex::Primitive(Primitive::Bool(_)) => Self::Bool,
+            Complex::Primitive(Primitive::Function(..)) => Self::Function,
+            Complex::Primitive(Primitive::Bytes(_)) => Self::Bytes,
+            Complex::Stack(_) => Self::Stack,
+            Complex::Map(_) => Self::Map,
+        }
+    }
+}
+/// Script and function of a function reference (`function@script` or `script::function`)
+pub fn function_reference(argument: &str) -> Option<(String, String)> {
+    match argument.find('@') {
+        Some(offset) => Some((
+            argument[offset + 1..].to_string(),
+            argument[..offset].to_string(),
+        )),
+        None => {
+            let offset = argument.find("::")?;
+            Some((
+                argument[..offset].to_string(),
+                argument[offset + 2..].to_string(),
+            ))
+        }
+    }
+}
+/// Whether an unquoted argument is a number literal (rather than a variable name)
+pub fn is_number(argument: &str) -> bool {
+    argument
+        .trim_start_matches('-')
+        .starts_with(|c: char| c.is_ascii_digit())
+        && argument.parse::<f64>().is_ok()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn json_round_trip() {
+        let json = r#"{"a":[1,2.5,"x",null,true,[[]]],"b":{"c":-3}}"#;
+        let value = Complex::from_json(serde_json::from_str(json).unwrap()).unwrap();
+        assert_eq!(value.to_string(), json);
+        let json = r#"{"a":1,"b":"x","c":null}"#;
+        let value = Complex::from_json(serde_json::from_str(json).unwrap()).unwrap();
+        assert_eq!(value.to_json().to_string(), json);
+        let json = r#"[1,2.5,"x",null,true]"#;
+        let value = Complex::from_json(serde_json::from_str(json).unwrap()).unwrap();
+        assert_eq!(value.to_string(), json);
+    }
+}